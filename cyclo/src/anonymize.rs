@@ -0,0 +1,113 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use clap::ValueEnum;
+use sha2::{Digest, Sha256};
+
+use crate::escape::json_string;
+use crate::labels::Labeler;
+
+
+/// What `--anonymize` hides
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Anonymize
+{
+    /// Replace every file and directory name with a hashed token, keeping the
+    /// directory structure and the extensions
+    Paths,
+}
+
+/// Stable token for a single file or directory name, e.g. `net.c` becomes
+/// `p3f2a9c1e.c`. The same name always gives the same token, so a tree keeps
+/// its shape and repeated runs can be compared
+pub fn token(name: &str) -> String
+{
+    /* a leading dot is part of the name, not an extension */
+    let (stem, extension) = match name.rfind('.')
+    {
+        Some(i) if i > 0 => (&name[..i], &name[i..]),
+        _ => (name, ""),
+    };
+
+    let digest = Sha256::digest(stem.as_bytes());
+    let hex: String = digest.iter()
+                            .take(4)
+                            .map(|b| format!("{:02x}", b))
+                            .collect();
+
+    format!("p{}{}", hex, extension)
+}
+
+/// Replaces the names in labels with tokens, remembering each one so the
+/// owning team can map them back with the file from `--anonymize-map`
+pub struct Anonymizer
+{
+    /// Keep the top node's name, which was chosen with `--root-label`
+    keep_root: bool,
+    /// anonymized label -> original label
+    mapping: BTreeMap<String, String>,
+}
+
+impl Anonymizer
+{
+    pub fn new(keep_root: bool) -> Anonymizer
+    {
+        Anonymizer
+        {
+            keep_root,
+            mapping: BTreeMap::new(),
+        }
+    }
+
+    /// Anonymize a label, and every directory above it
+    pub fn label(&mut self, label: &str) -> String
+    {
+        let mut anonymized = String::new();
+        let mut original = String::new();
+
+        for (i, name) in label.split('/').enumerate()
+        {
+            if i > 0
+            {
+                anonymized.push('/');
+                original.push('/');
+            }
+
+            if i == 0 && self.keep_root
+            {
+                anonymized.push_str(name);
+            }
+            else
+            {
+                anonymized.push_str(&token(name));
+            }
+            original.push_str(name);
+
+            self.mapping.entry(anonymized.clone()).or_insert_with(|| original.clone());
+        }
+
+        anonymized
+    }
+
+    /// Anonymized label for a path found while walking, for diagnostics and
+    /// `--dry-run`. Paths outside the root can't be labeled and are hidden
+    pub fn path(&mut self, labeler: &Labeler, path: &Path) -> PathBuf
+    {
+        match labeler.label(path)
+        {
+            Some(label) => PathBuf::from(self.label(&label)),
+            None => PathBuf::from("<outside root>"),
+        }
+    }
+
+    /// Write the mapping as a JSON object of anonymized label -> original
+    pub fn write_map(&self, path: &Path) -> io::Result<()>
+    {
+        let entries: Vec<String> = self.mapping.iter()
+                                               .map(|(anonymized, original)| format!("  {}: {}", json_string(anonymized), json_string(original)))
+                                               .collect();
+
+        fs::write(path, format!("{{\n{}\n}}\n", entries.join(",\n")))
+    }
+}
@@ -0,0 +1,272 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use clap::ValueEnum;
+use flate2::{Compression, GzBuilder};
+use sha2::{Digest, Sha256};
+
+
+/// Where compressed copies of the outputs go
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CompressMode
+{
+    /// Write `<file>.gz` next to the uncompressed file
+    Alongside,
+    /// Only write `<file>.gz`
+    Instead,
+}
+
+/// How output files are written
+#[derive(Debug, Default, Clone)]
+pub struct ArtifactOptions
+{
+    /// Write gzip compressed copies of the outputs
+    pub compress: Option<CompressMode>,
+    /// Write a `<file>.sha256` sidecar next to each file written
+    pub checksum: bool,
+}
+
+/// Passes writes through while hashing them, so the checksum of an artifact is
+/// computed as it is written rather than by reading it back
+struct HashingWriter<W: Write>
+{
+    inner: W,
+    hasher: Sha256,
+}
+
+impl<W: Write> Write for HashingWriter<W>
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize>
+    {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()>
+    {
+        self.inner.flush()
+    }
+}
+
+/// Append an extension to a path, e.g. cyclo.js -> cyclo.js.gz
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf
+{
+    let mut name = path.as_os_str().to_os_string();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// Write `<path>.sha256` in the format `sha256sum -c` understands
+fn write_checksum(path: &Path, hasher: Sha256) -> io::Result<()>
+{
+    let digest: String = hasher.finalize()
+                               .iter()
+                               .map(|b| format!("{:02x}", b))
+                               .collect();
+
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+
+    std::fs::write(with_suffix(path, ".sha256"), format!("{}  {}\n", digest, name))
+}
+
+/// Write the contents to a file, plus any compressed copy and checksum
+/// sidecars asked for. The report is already rendered in memory, so only the
+/// compressed copy and the hashes are produced as it is written; renderers
+/// don't stream
+pub fn write(path: &Path, contents: &[u8], options: &ArtifactOptions) -> io::Result<()>
+{
+    if options.compress != Some(CompressMode::Instead)
+    {
+        let mut out = HashingWriter { inner: BufWriter::new(File::create(path)?), hasher: Sha256::new() };
+        out.write_all(contents)?;
+        out.flush()?;
+
+        if options.checksum
+        {
+            write_checksum(path, out.hasher)?;
+        }
+    }
+
+    if options.compress.is_some()
+    {
+        let gz_path = with_suffix(path, ".gz");
+
+        let out = HashingWriter { inner: BufWriter::new(File::create(&gz_path)?), hasher: Sha256::new() };
+        /* no timestamp or file name in the gzip header so the compressed file is
+         * reproducible too */
+        let mut encoder = GzBuilder::new().mtime(0).write(out, Compression::default());
+        encoder.write_all(contents)?;
+
+        let mut out = encoder.finish()?;
+        out.flush()?;
+
+        if options.checksum
+        {
+            write_checksum(&gz_path, out.hasher)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// The path made absolute against the working directory, for messages. It is
+/// left as given if there is no working directory
+pub fn absolute(path: &Path) -> PathBuf
+{
+    std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Check that `path` can be written, without changing it: missing directories
+/// are created, an existing file is opened for appending, and a probe file is
+/// created and removed in its directory for any sidecars. Done before parsing
+/// so a read-only destination is reported straight away rather than after the
+/// whole run
+pub fn check_writable(path: &Path) -> io::Result<()>
+{
+    let directory = match path.parent()
+    {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+
+    fs::create_dir_all(directory)?;
+
+    if path.exists()
+    {
+        OpenOptions::new().append(true).open(path)?;
+    }
+
+    let probe = directory.join(format!(".cyclo-probe-{}", std::process::id()));
+
+    File::create(&probe)?;
+    fs::remove_file(&probe)
+}
+
+/// Save a report that couldn't be written to its destination in the temporary
+/// directory instead, so the run isn't lost. Returns where it went
+pub fn rescue(path: &Path, contents: &[u8]) -> io::Result<PathBuf>
+{
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let rescued = std::env::temp_dir().join(format!("cyclo-{}-{}", std::process::id(), name));
+
+    fs::write(&rescued, contents)?;
+
+    Ok(rescued)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    #[cfg(unix)]
+    use std::os::unix::fs::PermissionsExt;
+
+    /// Whether permissions are enforced here. They aren't for root, which
+    /// can write anywhere
+    #[cfg(unix)]
+    fn permissions_enforced(dir: &Path) -> bool
+    {
+        let probe = dir.join("probe");
+        let enforced = File::create(&probe).is_err();
+        let _ = fs::remove_file(probe);
+        enforced
+    }
+
+    #[test]
+    fn missing_parents_are_created()
+    {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a").join("b").join("cyclo.js");
+
+        check_writable(&path).unwrap();
+
+        assert!(path.parent().unwrap().is_dir());
+        assert!(!path.exists(), "the check must not create the file itself");
+        assert_eq!(fs::read_dir(path.parent().unwrap()).unwrap().count(), 0, "the probe must be removed");
+    }
+
+    #[test]
+    fn existing_files_are_left_alone()
+    {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cyclo.js");
+        fs::write(&path, "before").unwrap();
+
+        check_writable(&path).unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "before");
+    }
+
+    #[test]
+    fn parent_that_is_a_file_is_an_error()
+    {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("file");
+        fs::write(&file, "").unwrap();
+
+        assert!(check_writable(&file.join("cyclo.js")).is_err());
+        assert!(check_writable(&file.join("missing").join("cyclo.js")).is_err());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn read_only_directory_is_an_error()
+    {
+        let dir = tempfile::tempdir().unwrap();
+        let read_only = dir.path().join("read-only");
+        fs::create_dir(&read_only).unwrap();
+        fs::set_permissions(&read_only, fs::Permissions::from_mode(0o555)).unwrap();
+
+        if permissions_enforced(&read_only)
+        {
+            assert!(check_writable(&read_only.join("cyclo.js")).is_err());
+            assert!(check_writable(&read_only.join("missing").join("cyclo.js")).is_err());
+            assert!(write(&read_only.join("cyclo.js"), b"report", &ArtifactOptions::default()).is_err());
+        }
+
+        fs::set_permissions(&read_only, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn read_only_file_is_an_error()
+    {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cyclo.js");
+        fs::write(&path, "").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o444)).unwrap();
+
+        if OpenOptions::new().append(true).open(&path).is_err()
+        {
+            assert!(check_writable(&path).is_err());
+        }
+    }
+
+    #[test]
+    fn rescued_report_keeps_the_contents()
+    {
+        let rescued = rescue(Path::new("/nonexistent/cyclo.js"), b"report").unwrap();
+
+        assert!(rescued.starts_with(std::env::temp_dir()));
+        assert!(rescued.to_string_lossy().ends_with("cyclo.js"));
+        assert_eq!(fs::read(&rescued).unwrap(), b"report");
+
+        fs::remove_file(rescued).unwrap();
+    }
+
+    #[test]
+    fn sidecars_are_written_next_to_the_file()
+    {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cyclo.js");
+        let options = ArtifactOptions { compress: Some(CompressMode::Alongside), checksum: true };
+
+        write(&path, b"report", &options).unwrap();
+
+        assert_eq!(fs::read(&path).unwrap(), b"report");
+        assert!(with_suffix(&path, ".gz").is_file());
+        assert!(fs::read_to_string(with_suffix(&path, ".sha256")).unwrap().ends_with("  cyclo.js\n"));
+        assert!(fs::read_to_string(with_suffix(&path, ".gz.sha256")).unwrap().ends_with("  cyclo.js.gz\n"));
+    }
+}
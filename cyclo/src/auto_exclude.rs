@@ -0,0 +1,119 @@
+use std::path::Path;
+
+
+/// Directories an ecosystem's tools fill with downloaded or built files,
+/// skipped when the file that marks a project of that ecosystem is found at
+/// the root
+pub struct AutoRule
+{
+    /// File at the root that shows the ecosystem is in use
+    pub marker: &'static str,
+    /// Directory names skipped anywhere in the tree when the marker is found
+    pub names: &'static [&'static str],
+}
+
+/// Every rule, checked in this order
+pub const RULES: [AutoRule; 14] = [
+    AutoRule { marker: "package.json", names: &["node_modules", "bower_components", ".next", ".nuxt"] },
+    AutoRule { marker: "Podfile", names: &["Pods"] },
+    AutoRule { marker: "Package.swift", names: &[".build", "DerivedData"] },
+    AutoRule { marker: "pyproject.toml", names: &[".venv", "venv", "__pycache__", ".tox"] },
+    AutoRule { marker: "setup.py", names: &[".venv", "venv", "__pycache__", ".tox"] },
+    AutoRule { marker: "requirements.txt", names: &[".venv", "venv", "__pycache__"] },
+    AutoRule { marker: "WORKSPACE", names: &["bazel-bin", "bazel-out", "bazel-testlogs"] },
+    AutoRule { marker: "WORKSPACE.bazel", names: &["bazel-bin", "bazel-out", "bazel-testlogs"] },
+    AutoRule { marker: "MODULE.bazel", names: &["bazel-bin", "bazel-out", "bazel-testlogs"] },
+    AutoRule { marker: "pom.xml", names: &["target"] },
+    AutoRule { marker: "build.gradle", names: &[".gradle", "build"] },
+    AutoRule { marker: "build.gradle.kts", names: &[".gradle", "build"] },
+    AutoRule { marker: "composer.json", names: &["vendor"] },
+    AutoRule { marker: "CMakeLists.txt", names: &["CMakeFiles", "cmake-build-debug", "cmake-build-release"] },
+];
+
+/// A directory name to skip, and the marker that switched it on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AutoIgnore
+{
+    pub name: String,
+    pub marker: String,
+}
+
+/// The directory names to skip for the markers found at `root`. A name wanted
+/// by several markers is attributed to the first
+pub fn detect(root: &Path) -> Vec<AutoIgnore>
+{
+    let mut ignores: Vec<AutoIgnore> = Vec::new();
+
+    for rule in RULES.iter().filter(|rule| root.join(rule.marker).is_file())
+    {
+        for name in rule.names
+        {
+            if !ignores.iter().any(|ignore| ignore.name == *name)
+            {
+                ignores.push(AutoIgnore { name: name.to_string(), marker: rule.marker.to_string() });
+            }
+        }
+    }
+
+    ignores
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn each_marker_switches_on_its_own_names()
+    {
+        for rule in &RULES
+        {
+            let dir = tempfile::tempdir().unwrap();
+            fs::write(dir.path().join(rule.marker), "").unwrap();
+
+            let found = detect(dir.path());
+            let names: Vec<&str> = found.iter().map(|ignore| ignore.name.as_str()).collect();
+
+            assert_eq!(names, rule.names, "{}", rule.marker);
+            assert!(found.iter().all(|ignore| ignore.marker == rule.marker), "{}", rule.marker);
+        }
+    }
+
+    #[test]
+    fn no_marker_no_names()
+    {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("node_modules")).unwrap();
+
+        assert_eq!(detect(dir.path()), Vec::new());
+    }
+
+    #[test]
+    fn markers_only_count_at_the_root_as_files()
+    {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("web").join("package.json")).unwrap();
+        fs::write(dir.path().join("web").join("Podfile"), "").unwrap();
+
+        assert_eq!(detect(dir.path()), Vec::new());
+    }
+
+    #[test]
+    fn shared_names_go_to_the_first_marker()
+    {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("setup.py"), "").unwrap();
+        fs::write(dir.path().join("pyproject.toml"), "").unwrap();
+        fs::write(dir.path().join("package.json"), "").unwrap();
+
+        let found = detect(dir.path());
+        let marker = |name: &str| found.iter().find(|i| i.name == name).map(|i| i.marker.as_str());
+
+        /* pyproject.toml is listed before setup.py, and each name only once */
+        assert_eq!(found.len(), 8);
+        assert_eq!(marker(".venv"), Some("pyproject.toml"));
+        assert_eq!(marker(".tox"), Some("pyproject.toml"));
+        assert_eq!(marker("node_modules"), Some("package.json"));
+    }
+}
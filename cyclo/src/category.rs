@@ -0,0 +1,120 @@
+use std::path::Path;
+use clap::ValueEnum;
+use regex::Regex;
+
+use crate::vendored;
+
+
+/// File names treated as generated unless `--generated-pattern` is given:
+/// the output of Qt's moc and resource compiler
+pub const DEFAULT_GENERATED: [&str; 2] = [r"^moc_.*\.cpp$", r"^qrc_.*\.cpp$"];
+
+/// What is done with the files put in a category
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CategoryMode
+{
+    /// Keep them where they are, marked in the hover text
+    Include,
+    /// Leave them out of the chart
+    Exclude,
+    /// Move them under a branch of their own below the top node
+    Separate,
+}
+
+/// Files that are built with ours but weren't written here
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Category
+{
+    /// Written by a tool during the build, e.g. `moc_window.cpp`
+    Generated,
+    /// Copied in from another project, see `vendored::detect`
+    Vendored,
+}
+
+impl Category
+{
+    /// Name used in hover text and as the branch for `separate`
+    pub fn name(&self) -> &'static str
+    {
+        match self
+        {
+            Category::Generated => "generated",
+            Category::Vendored => "vendored",
+        }
+    }
+
+    /// Label of a file moved under the category's branch, e.g.
+    /// `root/lib/zlib.c` becomes `root/vendored/lib/zlib.c`
+    pub fn branch_label(&self, label: &str) -> String
+    {
+        match label.split_once('/')
+        {
+            Some((top, rest)) => format!("{}/{}/{}", top, self.name(), rest),
+            None => format!("{}/{}", self.name(), label),
+        }
+    }
+}
+
+/// The category a file was put in, and why
+pub struct Classified
+{
+    pub category: Category,
+    pub mode: CategoryMode,
+    /// The pattern or copyright line that matched
+    pub reason: String,
+}
+
+/// Sorts files into categories. Each file gets at most one, checked in order:
+///
+/// 1. generated, by file name, since generated files often carry the license
+///    header of the tool that wrote them
+/// 2. vendored, by license header
+#[derive(Debug, Clone, Default)]
+pub struct Classifier
+{
+    pub generated: Option<(CategoryMode, Vec<Regex>)>,
+    /// Mode, and the text of our own copyright lines
+    pub vendored: Option<(CategoryMode, Vec<String>)>,
+}
+
+impl Classifier
+{
+    pub fn classify(&self, path: &Path) -> Option<Classified>
+    {
+        if let Some((mode, patterns)) = &self.generated
+        {
+            let name = path.file_name()?.to_string_lossy();
+
+            if let Some(pattern) = patterns.iter().find(|p| p.is_match(&name))
+            {
+                return Some(Classified { category: Category::Generated, mode: *mode, reason: pattern.to_string() });
+            }
+        }
+
+        if let Some((mode, ours)) = &self.vendored
+        {
+            if let Some(line) = vendored::detect(path, ours)
+            {
+                return Some(Classified { category: Category::Vendored, mode: *mode, reason: line });
+            }
+        }
+
+        None
+    }
+
+    /// The category of a file if its mode leaves it out. Nothing is read
+    /// unless some category is excluded
+    pub fn excluded(&self, path: &Path) -> Option<Category>
+    {
+        let modes = [self.generated.as_ref().map(|(mode, _)| *mode), self.vendored.as_ref().map(|(mode, _)| *mode)];
+
+        if !modes.contains(&Some(CategoryMode::Exclude))
+        {
+            return None;
+        }
+
+        self.classify(path)
+            .filter(|classified| classified.mode == CategoryMode::Exclude)
+            .map(|classified| classified.category)
+    }
+}
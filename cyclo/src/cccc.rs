@@ -0,0 +1,115 @@
+use crate::escape::{html, strip_control};
+use crate::report::Record;
+
+
+/// Text for an element. Control characters aren't allowed in XML 1.0 at all,
+/// so they are replaced rather than escaped
+fn text(s: &str) -> String
+{
+    html(&strip_control(s))
+}
+
+/// McCabe's number for a file as CCCC gives it: the decisions in all of its
+/// functions, rather than cyclo's mean per function
+fn mccabe(record: &Record) -> u64
+{
+    (record.cc * record.function_count as f64).round() as u64
+}
+
+/// Render the files as a CCCC-style XML report, each file being a module.
+/// Modules are sorted by path so the output only changes with the results.
+/// No timestamp is written, so the report stays reproducible
+pub fn render(records: &[Record]) -> String
+{
+    let mut files: Vec<&Record> = records.iter().filter(|r| !r.is_dir).collect();
+    files.sort_by(|a, b| a.relative_path().cmp(b.relative_path()));
+
+    let nloc: u64 = files.iter().map(|f| f.nloc).sum();
+    let mvg: u64 = files.iter().map(|f| mccabe(f)).sum();
+
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<CCCC_Project>\n");
+
+    out.push_str(&format!("  <project_summary>\n    <number_of_modules value=\"{}\"/>\n    <lines_of_code value=\"{}\"/>\n    <McCabes_cyclomatic_complexity value=\"{}\"/>\n  </project_summary>\n",
+                          files.len(), nloc, mvg));
+
+    out.push_str("  <procedural_summary>\n");
+
+    for file in &files
+    {
+        out.push_str(&format!("    <module>\n      <name>{}</name>\n      <lines_of_code value=\"{}\"/>\n      <McCabes_cyclomatic_complexity value=\"{}\"/>\n    </module>\n",
+                              text(file.relative_path()), file.nloc, mccabe(file)));
+    }
+
+    out.push_str("  </procedural_summary>\n</CCCC_Project>\n");
+
+    out
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn record(label: &str, is_dir: bool, nloc: u64, cc: f64, function_count: u64) -> Record
+    {
+        Record
+        {
+            is_dir,
+            label: label.to_string(),
+            parent: label.rsplit_once('/').map(|(parent, _)| parent.to_string()).unwrap_or_default(),
+            nloc,
+            cc,
+            compile_time_cc: 0.0,
+            function_count,
+            functions: Vec::new(),
+            had_invalid_utf8: false,
+            hovertext: String::new(),
+        }
+    }
+
+    #[test]
+    fn modules_are_files_sorted_by_path()
+    {
+        let records = [record("test/net/tcp.c", false, 100, 2.5, 4),
+                       record("test", true, 0, 0.0, 0),
+                       record("test/a<b>&.c", false, 10, 1.0 / 3.0, 3),
+                       record("test/net", true, 0, 0.0, 0)];
+
+        assert_eq!(render(&records), r#"<?xml version="1.0" encoding="UTF-8"?>
+<CCCC_Project>
+  <project_summary>
+    <number_of_modules value="2"/>
+    <lines_of_code value="110"/>
+    <McCabes_cyclomatic_complexity value="11"/>
+  </project_summary>
+  <procedural_summary>
+    <module>
+      <name>a&lt;b&gt;&amp;.c</name>
+      <lines_of_code value="10"/>
+      <McCabes_cyclomatic_complexity value="1"/>
+    </module>
+    <module>
+      <name>net/tcp.c</name>
+      <lines_of_code value="100"/>
+      <McCabes_cyclomatic_complexity value="10"/>
+    </module>
+  </procedural_summary>
+</CCCC_Project>
+"#);
+    }
+
+    #[test]
+    fn an_empty_project_has_no_modules()
+    {
+        let out = render(&[record("test", true, 0, 0.0, 0)]);
+
+        assert!(out.contains("<number_of_modules value=\"0\"/>"));
+        assert!(out.contains("<procedural_summary>\n  </procedural_summary>"));
+    }
+
+    #[test]
+    fn control_characters_are_replaced()
+    {
+        assert_eq!(text("a\u{1}b\tc"), "a?b?c");
+    }
+}
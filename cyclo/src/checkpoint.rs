@@ -0,0 +1,168 @@
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+use crate::file_parser::{FileParser, MacroFunction, ParseOptions};
+
+
+/// Results of a file finished in an earlier run
+pub struct Entry
+{
+    /// Size and modification time when it was parsed
+    fingerprint: String,
+    pub nloc: u64,
+    pub cc: f64,
+    pub compile_time_cc: Option<f64>,
+    pub functions: u64,
+    pub macros: Vec<MacroFunction>,
+    pub had_invalid_utf8: bool,
+}
+
+/// A file the results of each parsed file are appended to as soon as they are
+/// known, so a run that is cut short can be picked up where it stopped with
+/// `--resume` instead of reading everything again
+pub struct Checkpoint
+{
+    file: File,
+    /// relative path -> results from the earlier run
+    done: HashMap<String, Entry>,
+}
+
+/// Identifies what the results depend on besides the files themselves. A
+/// checkpoint written with anything else different is started over
+fn header(root: &Path, options: &ParseOptions) -> String
+{
+    format!("# cyclo checkpoint v2 {} {} {:?}", env!("CARGO_PKG_VERSION"), root.display(), options)
+}
+
+/// Cheap stand-in for the contents of a file, so a file that changed since it
+/// was checkpointed is parsed again. Hashing would mean reading everything,
+/// which is what resuming avoids
+pub fn fingerprint(path: &Path) -> Option<String>
+{
+    let metadata = fs::metadata(path).ok()?;
+    let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+
+    Some(format!("{}:{}", metadata.len(), modified.as_nanos()))
+}
+
+/// Parse one line written by `Checkpoint::record`
+fn parse_entry(line: &str) -> Option<(String, Entry)>
+{
+    let fields: Vec<&str> = line.split('\t').collect();
+
+    if fields.len() != 8
+    {
+        return None;
+    }
+
+    let macros = fields[6].split(',')
+                          .filter(|m| !m.is_empty())
+                          .map(|m| {
+                              let (name, cc) = m.rsplit_once('=')?;
+                              Some(MacroFunction { name: name.to_string(), cc: cc.parse().ok()? })
+                          })
+                          .collect::<Option<Vec<MacroFunction>>>()?;
+
+    let entry = Entry
+    {
+        fingerprint: fields[1].to_string(),
+        nloc: fields[2].parse().ok()?,
+        cc: fields[3].parse().ok()?,
+        compile_time_cc: if fields[4] == "-" { None } else { Some(fields[4].parse().ok()?) },
+        functions: fields[5].parse().ok()?,
+        macros,
+        had_invalid_utf8: fields[7] == "1",
+    };
+
+    Some((fields[0].to_string(), entry))
+}
+
+impl Checkpoint
+{
+    /// Open the checkpoint at `path`. With `resume`, the results already in it
+    /// are kept if they were made from the same root and counting rules; a
+    /// line cut off by the interruption is dropped. Otherwise it starts empty
+    pub fn open(path: &Path, root: &Path, options: &ParseOptions, resume: bool) -> io::Result<Checkpoint>
+    {
+        let header = header(root, options);
+        let mut done = HashMap::new();
+
+        if resume && path.exists()
+        {
+            let mut lines = BufReader::new(File::open(path)?).lines();
+
+            if lines.next().transpose()?.as_deref() == Some(header.as_str())
+            {
+                done = lines.map_while(Result::ok)
+                            .filter_map(|line| parse_entry(&line))
+                            .collect();
+            }
+        }
+
+        /* rewrite what is kept, which also drops a partial last line */
+        let mut file = OpenOptions::new().write(true).create(true).truncate(true).open(path)?;
+        writeln!(file, "{}", header)?;
+
+        let mut checkpoint = Checkpoint { file, done: HashMap::new() };
+
+        for (relative, entry) in done
+        {
+            checkpoint.write(&relative, &entry)?;
+            checkpoint.done.insert(relative, entry);
+        }
+
+        Ok(checkpoint)
+    }
+
+    /// Results from the earlier run, if the file hasn't changed since
+    pub fn get(&self, relative: &str, fingerprint: &str) -> Option<&Entry>
+    {
+        self.done.get(relative).filter(|entry| entry.fingerprint == fingerprint)
+    }
+
+    /// Append the results of a file that was just parsed
+    pub fn record(&mut self, relative: &str, fingerprint: &str, file: &FileParser) -> io::Result<()>
+    {
+        let entry = Entry
+        {
+            fingerprint: fingerprint.to_string(),
+            nloc: file.nloc.unwrap_or(0),
+            cc: file.cc.unwrap_or(0.0),
+            compile_time_cc: file.compile_time_cc,
+            functions: file.functions,
+            macros: file.macros.clone(),
+            had_invalid_utf8: file.had_invalid_utf8,
+        };
+
+        self.write(relative, &entry)
+    }
+
+    fn write(&mut self, relative: &str, entry: &Entry) -> io::Result<()>
+    {
+        /* such names can't be written on one line, they are just parsed again */
+        if relative.contains(['\t', '\n', '\r'])
+        {
+            return Ok(());
+        }
+
+        let macros: Vec<String> = entry.macros.iter()
+                                              .map(|m| format!("{}={}", m.name, m.cc))
+                                              .collect();
+
+        /* f64 is written in its shortest form that reads back exactly */
+        writeln!(self.file, "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                 relative,
+                 entry.fingerprint,
+                 entry.nloc,
+                 entry.cc,
+                 entry.compile_time_cc.map(|cc| cc.to_string()).unwrap_or_else(|| "-".to_string()),
+                 entry.functions,
+                 macros.join(","),
+                 if entry.had_invalid_utf8 { 1 } else { 0 })?;
+
+        self.file.flush()
+    }
+}
@@ -0,0 +1,55 @@
+use sha2::{Digest, Sha256};
+
+use crate::escape::json_string;
+use crate::report::{self, Record};
+
+
+/// The one check every issue is reported under
+const CHECK_NAME: &str = "cyclomatic-complexity";
+
+/// Severity from how far over the threshold the mean cc is
+fn severity(cc: f64, threshold: f64) -> &'static str
+{
+    let ratio = if threshold > 0.0 { cc / threshold } else { f64::INFINITY };
+
+    match ratio
+    {
+        r if r <= 1.5 => "minor",
+        r if r <= 2.0 => "major",
+        r if r <= 4.0 => "critical",
+        _ => "blocker",
+    }
+}
+
+/// Identifies an issue across runs, so it is only new once. `function` is
+/// empty for an issue about a whole file
+fn fingerprint(path: &str, function: &str) -> String
+{
+    let digest = Sha256::digest(format!("{}\0{}\0{}", CHECK_NAME, path, function).as_bytes());
+
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Render a Code Climate issue array, as GitLab's code quality report takes,
+/// with an issue for each file whose mean cc is above `threshold`, worst first.
+/// Complexity is only known per file, so issues point at the first line. At
+/// most `max_findings` issues are written
+pub fn render(records: &[Record], threshold: f64, max_findings: Option<usize>) -> String
+{
+    let (findings, _) = report::findings(records, threshold, max_findings);
+
+    let issues: Vec<String> = findings.iter()
+                                      .map(|r| {
+                                          let path = r.relative_path();
+
+                                          format!(r#"  {{"type":"issue","check_name":"{}","description":{},"categories":["Complexity"],"severity":"{}","fingerprint":"{}","location":{{"path":{},"lines":{{"begin":1,"end":1}}}}}}"#,
+                                                  CHECK_NAME,
+                                                  json_string(&format!("mean function cyclomatic complexity {:.2} is above {}", r.cc, threshold)),
+                                                  severity(r.cc, threshold),
+                                                  fingerprint(path, ""),
+                                                  json_string(path))
+                                      })
+                                      .collect();
+
+    format!("[\n{}\n]\n", issues.join(",\n"))
+}
@@ -0,0 +1,166 @@
+/// Share of values at each end of the range that are allowed to saturate the
+/// scale, so a few outliers don't flatten everything else into one color
+const OUTLIER_SHARE: f64 = 0.05;
+
+/// Settings for the chart colors
+pub struct ColorOptions<'a>
+{
+    /// A plotly colorscale name, e.g. `Greens`
+    pub scale: &'a str,
+}
+
+impl Default for ColorOptions<'_>
+{
+    fn default() -> Self
+    {
+        ColorOptions
+        {
+            scale: "Greens",
+        }
+    }
+}
+
+/// The color domain handed to plotly
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColorSpec
+{
+    /// Value shown in the middle of the scale
+    pub cmid: f64,
+    /// Lowest value with its own color. Anything lower is clamped to it
+    pub cmin: f64,
+    /// Highest value with its own color. Anything higher is clamped to it
+    pub cmax: f64,
+    pub scale: String,
+}
+
+/// Value at `share` of the way through sorted values, nearest rank
+fn percentile(sorted: &[f64], share: f64) -> f64
+{
+    let rank = (share * (sorted.len() - 1) as f64).round() as usize;
+
+    sorted[rank.min(sorted.len() - 1)]
+}
+
+/// Work out the color domain for a set of values, which should be the files
+/// only since directories have no metric of their own.
+///
+/// - the midpoint is the mean, or 0 when any value is negative so that deltas
+///   are colored by their sign
+/// - the range covers everything but the outermost 5% on each side, and is
+///   symmetric around the midpoint
+/// - non-finite values are ignored, and empty or constant input still gives
+///   a non-empty range
+pub fn prepare(values: &[f64], options: &ColorOptions) -> ColorSpec
+{
+    let mut sorted: Vec<f64> = values.iter().copied().filter(|v| v.is_finite()).collect();
+    sorted.sort_by(f64::total_cmp);
+
+    if sorted.is_empty()
+    {
+        return ColorSpec { cmid: 0.5, cmin: 0.0, cmax: 1.0, scale: options.scale.to_string() };
+    }
+
+    let cmid = if sorted[0] < 0.0
+    {
+        0.0
+    }
+    else
+    {
+        sorted.iter().sum::<f64>() / sorted.len() as f64
+    };
+
+    let low = percentile(&sorted, OUTLIER_SHARE);
+    let high = percentile(&sorted, 1.0 - OUTLIER_SHARE);
+
+    let mut half = (cmid - low).max(high - cmid);
+
+    /* all values the same, plotly needs some room to pick a color */
+    if half <= f64::EPSILON
+    {
+        half = 0.5;
+    }
+
+    ColorSpec
+    {
+        cmid,
+        cmin: cmid - half,
+        cmax: cmid + half,
+        scale: options.scale.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn spec(values: &[f64]) -> ColorSpec
+    {
+        prepare(values, &ColorOptions::default())
+    }
+
+    fn domain(spec: &ColorSpec) -> (f64, f64, f64)
+    {
+        (spec.cmin, spec.cmid, spec.cmax)
+    }
+
+    #[test]
+    fn empty_input_has_a_unit_range()
+    {
+        assert_eq!(domain(&spec(&[])), (0.0, 0.5, 1.0));
+    }
+
+    #[test]
+    fn single_value_is_centered()
+    {
+        assert_eq!(domain(&spec(&[4.0])), (3.5, 4.0, 4.5));
+    }
+
+    #[test]
+    fn equal_values_still_have_room()
+    {
+        assert_eq!(domain(&spec(&[2.0, 2.0, 2.0])), (1.5, 2.0, 2.5));
+        assert_eq!(domain(&spec(&[0.0; 10])), (-0.5, 0.0, 0.5));
+    }
+
+    #[test]
+    fn outliers_saturate_instead_of_flattening_the_scale()
+    {
+        let mut values: Vec<f64> = (1..=99).map(|v| v as f64).collect();
+        values.push(1000.0);
+
+        let spec = spec(&values);
+
+        /* the mean moves, but the range stops well short of the outlier */
+        assert_eq!(domain(&spec), (6.0, 59.5, 113.0));
+    }
+
+    #[test]
+    fn negative_deltas_are_centered_on_zero()
+    {
+        assert_eq!(domain(&spec(&[-3.0, -1.0, 0.0, 2.0, 5.0])), (-5.0, 0.0, 5.0));
+        assert_eq!(domain(&spec(&[-2.0, -1.0])), (-2.0, 0.0, 2.0));
+    }
+
+    #[test]
+    fn range_is_symmetric_around_the_midpoint()
+    {
+        let spec = spec(&[1.0, 2.0, 3.0, 10.0]);
+
+        assert_eq!(spec.cmid - spec.cmin, spec.cmax - spec.cmid);
+    }
+
+    #[test]
+    fn non_finite_values_are_ignored()
+    {
+        assert_eq!(domain(&spec(&[f64::NAN, 1.0, f64::INFINITY, 3.0])), (1.0, 2.0, 3.0));
+        assert_eq!(domain(&spec(&[f64::NAN, f64::NEG_INFINITY])), (0.0, 0.5, 1.0));
+    }
+
+    #[test]
+    fn scale_name_is_passed_through()
+    {
+        assert_eq!(spec(&[1.0]).scale, "Greens");
+        assert_eq!(prepare(&[1.0], &ColorOptions { scale: "RdBu" }).scale, "RdBu");
+    }
+}
@@ -0,0 +1,143 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use clap::ValueEnum;
+use snafu::prelude::*;
+use toml::{Table, Value};
+
+use crate::gate::Threshold;
+use crate::report::Format;
+
+
+/// Name of the project config file, looked for from the analyzed path up
+pub const FILE_NAME: &str = "cyclo.toml";
+
+/// Errors returned when a config file can't be used
+#[derive(Debug, Snafu)]
+pub enum ConfigError
+{
+    /// The file exists but can't be read
+    #[snafu(display("could not read '{path}': {source}"))]
+    Unreadable { path: String, source: std::io::Error },
+    /// The file isn't TOML
+    #[snafu(display("'{path}' is not valid TOML: {source}"))]
+    Invalid { path: String, source: toml::de::Error },
+    /// A known key has a value of the wrong kind
+    #[snafu(display("'{key}' in '{path}' {problem}"))]
+    BadValue { path: String, key: String, problem: String },
+}
+
+/// Settings from a `cyclo.toml`. Each is None when the file doesn't set it,
+/// and command line flags win over all of them
+#[derive(Debug, Default)]
+pub struct Config
+{
+    /// Where the settings were read from, None without a file
+    pub path: Option<PathBuf>,
+    pub exclude: Option<Vec<String>>,
+    pub include: Option<Vec<String>>,
+    pub extensions: Option<Vec<String>>,
+    /// Replaces the default ignore set
+    pub default_ignores: Option<Vec<String>>,
+    /// Added to the default ignore set
+    pub extra_ignores: Option<Vec<String>>,
+    pub fail_over: Option<Threshold>,
+    /// Relative to the directory the file is in
+    pub output: Option<PathBuf>,
+    pub format: Option<Format>,
+    /// Keys that aren't settings, reported rather than silently dropped
+    pub unknown: Vec<String>,
+}
+
+/// The nearest `cyclo.toml` in the directory of `start` or any above it
+pub fn discover(start: &Path) -> Option<PathBuf>
+{
+    let dir = if start.is_dir() { start } else { start.parent()? };
+
+    dir.ancestors()
+       .map(|dir| dir.join(FILE_NAME))
+       .find(|path| path.is_file())
+}
+
+/// A string, or a list of strings for keys that take several
+fn strings(value: &Value) -> Option<Vec<String>>
+{
+    match value
+    {
+        Value::String(s) => Some(vec![s.clone()]),
+        Value::Array(items) => items.iter().map(|item| item.as_str().map(str::to_string)).collect(),
+        _ => None,
+    }
+}
+
+/// Read the config file at `path`
+pub fn load(path: &Path) -> Result<Config, ConfigError>
+{
+    let shown = path.display().to_string();
+
+    let text = fs::read_to_string(path).context(UnreadableSnafu { path: &shown })?;
+    let table: Table = text.parse().context(InvalidSnafu { path: &shown })?;
+
+    let bad = |key: &str, problem: &str| BadValueSnafu { path: &shown, key, problem }.build();
+
+    let mut config = Config { path: Some(path.to_path_buf()), ..Config::default() };
+
+    for (key, value) in &table
+    {
+        match key.as_str()
+        {
+            "exclude" => config.exclude = Some(strings(value).ok_or_else(|| bad(key, "must be a string or a list of strings"))?),
+            "include" => config.include = Some(strings(value).ok_or_else(|| bad(key, "must be a string or a list of strings"))?),
+            "extensions" => config.extensions = Some(strings(value).ok_or_else(|| bad(key, "must be a string or a list of strings"))?),
+            "default_ignores" => config.default_ignores = Some(strings(value).ok_or_else(|| bad(key, "must be a string or a list of strings"))?),
+            "extra_ignores" => config.extra_ignores = Some(strings(value).ok_or_else(|| bad(key, "must be a string or a list of strings"))?),
+            "fail_over" => {
+                let threshold = match value
+                {
+                    Value::Integer(n) => Ok(Threshold::Absolute(*n as f64)),
+                    Value::Float(n) => Ok(Threshold::Absolute(*n)),
+                    Value::String(s) => s.parse(),
+                    _ => Err("must be a number or a percentile like \"p95\"".to_string()),
+                };
+
+                config.fail_over = Some(threshold.map_err(|e| bad(key, &e))?);
+            },
+            "output" => {
+                let output = value.as_str().ok_or_else(|| bad(key, "must be a path"))?;
+                let dir = path.parent().unwrap_or(Path::new("."));
+
+                config.output = Some(dir.join(output));
+            },
+            "format" => {
+                let format = value.as_str().ok_or_else(|| bad(key, "must be a string"))?;
+
+                config.format = Some(Format::from_str(format, false).map_err(|_| bad(key, &format!("'{}' is not a format", format)))?);
+            },
+            _ => config.unknown.push(key.clone()),
+        }
+    }
+
+    Ok(config)
+}
+
+/// The effective settings for `--print-config`, one `key = value` line each
+/// followed by where the value came from. Unset keys are commented out
+pub fn render(settings: &[(&str, Option<Value>, String)], unknown: &[String]) -> String
+{
+    let mut out = String::new();
+
+    for (key, value, origin) in settings
+    {
+        match value
+        {
+            Some(value) => out.push_str(&format!("{} = {}  # {}\n", key, value, origin)),
+            None => out.push_str(&format!("# {} is not set\n", key)),
+        }
+    }
+
+    for key in unknown
+    {
+        out.push_str(&format!("# unknown key '{}' is ignored\n", key));
+    }
+
+    out
+}
@@ -0,0 +1,41 @@
+use crate::report::Record;
+
+
+/// Column names, written as the first row
+const HEADER: &str = "kind,path,parent,nloc,cc,functions";
+
+/// Quote a field if it holds anything that would end it early, doubling any
+/// quotes inside, as RFC 4180 expects
+fn field(s: &str) -> String
+{
+    if s.contains([',', '"', '\n', '\r'])
+    {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    }
+    else
+    {
+        s.to_string()
+    }
+}
+
+/// Render the records as CSV, one row per file and directory. Directories
+/// have no metrics of their own, so the `kind` column tells them apart for
+/// filtering
+pub fn render(records: &[Record]) -> String
+{
+    let mut out = String::from(HEADER);
+    out.push_str("\r\n");
+
+    for r in records
+    {
+        out.push_str(&format!("{},{},{},{},{:.4},{}\r\n",
+                              r.kind(),
+                              field(&r.label),
+                              field(&r.parent),
+                              r.nloc,
+                              r.cc,
+                              r.function_count));
+    }
+
+    out
+}
@@ -0,0 +1,426 @@
+use std::fmt;
+use std::fs;
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use clap::ValueEnum;
+use indicatif::{ProgressBar, ProgressStyle};
+
+use crate::escape::{json_string, strip_control};
+use crate::root::display;
+
+
+/// How diagnostics are written
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorsFormat
+{
+    /// One readable line per error or warning
+    Human,
+    /// One JSON object per line for every diagnostic, including info
+    Json,
+}
+
+/// How much is written to stderr besides errors, from `-q` and `-v`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verbosity
+{
+    /// Errors only
+    Quiet,
+    /// Warnings too, and a one-line summary at the end
+    Normal,
+    /// Each file as it is analyzed, with its nloc and cc
+    Verbose,
+    /// Info diagnostics too, like each skipped entry and why
+    Debug,
+}
+
+/// Stable identifier for each kind of diagnostic. The string form is part of
+/// the JSON output and must not change once released
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Code
+{
+    /// `E_IO`: a file could not be opened or read
+    Io,
+    /// `E_PARSE`: a file could not be parsed, e.g. because of a bad extension
+    Parse,
+    /// `E_PATH`: a `--path` doesn't exist or can't be read, or a file could
+    /// not be given a label, e.g. because it was reached through a symlink
+    /// pointing outside of the analyzed root
+    Path,
+    /// `W_UNSUPPORTED`: files were skipped because their language is not
+    /// supported. The message holds the breakdown by extension
+    Unsupported,
+    /// `W_MACRO_SKIPPED`: some function-like macro bodies in a file couldn't
+    /// be parsed and weren't counted as pseudo-functions
+    MacroSkipped,
+    /// `W_CASE_COLLISION`: labels differ only in case, so they collide on
+    /// case-insensitive filesystems. The message lists the spellings
+    CaseCollision,
+    /// `W_PATH_REWRITE`: some labels matched no `--path-rewrite`, or matched
+    /// rewrites that disagree. The message holds the counts
+    RewriteUnmatched,
+    /// `E_PATH_REWRITE_COLLISION`: a file was rewritten to a label another
+    /// file already has, and was left out
+    RewriteCollision,
+    /// `W_WEBHOOK`: the summary couldn't be posted to `--webhook-url`
+    Webhook,
+    /// `E_WEBHOOK`: as `W_WEBHOOK`, with `--webhook-required` set
+    WebhookRequired,
+    /// `I_SKIPPED`: an entry was left out by the planner. The message holds
+    /// the filter that removed it
+    Skipped,
+    /// `I_GENERATED`: `--detect-generated` matched a file name. The message
+    /// holds the pattern
+    Generated,
+    /// `I_VENDORED`: `--detect-vendored` found a license header naming
+    /// another project. The message holds the copyright line
+    Vendored,
+    /// `I_AUTO_EXCLUDE`: a marker file at the root switched on an
+    /// auto-exclude rule. The message names the marker and directories
+    AutoExclude,
+    /// `I_EXCLUDED`: how many entries each `--exclude` pattern left out
+    Excluded,
+    /// `W_EXCLUDE_UNMATCHED`: an `--exclude` pattern matched nothing, which
+    /// usually means it is written wrong
+    ExcludeUnmatched,
+    /// `E_FAIL_ABOVE`: a file's mean cc is above `--fail-above`, or the mean
+    /// over every file is above `--fail-over-mean`. The message holds the
+    /// limit
+    FailAbove,
+    /// `E_GATE`: files were above `--fail-above`, so the run exits with 3.
+    /// The message holds how many and the resolved limit
+    Gate,
+    /// `W_FILES_FROM`: a path listed in `--files-from` doesn't exist or
+    /// can't be read, and was left out
+    FilesFrom,
+    /// `E_FILES_FROM`: the `--files-from` list can't be read, or none of the
+    /// paths in it could be analyzed
+    FilesFromUnreadable,
+    /// `W_INVALID_UTF8`: a file isn't valid UTF-8 and `--strict-utf8` left
+    /// it out
+    InvalidUtf8,
+    /// `E_CHECKPOINT`: `--checkpoint` couldn't be opened or appended to, which
+    /// stops the run
+    Checkpoint,
+    /// `E_WRITE`: an output can't be written. Checked before parsing, and
+    /// again when writing; a report that fails then is saved elsewhere and
+    /// the message says where
+    Write,
+    /// `W_CONFIG_KEY`: the config file has a key cyclo doesn't know, usually
+    /// misspelled. The message names it
+    ConfigKey,
+    /// `E_CONFIG`: the config file can't be loaded, or an `--exclude` or
+    /// `--include` pattern is invalid
+    Config,
+}
+
+impl Code
+{
+    pub fn as_str(&self) -> &'static str
+    {
+        match self
+        {
+            Code::Io => "E_IO",
+            Code::Parse => "E_PARSE",
+            Code::Path => "E_PATH",
+            Code::Unsupported => "W_UNSUPPORTED",
+            Code::MacroSkipped => "W_MACRO_SKIPPED",
+            Code::CaseCollision => "W_CASE_COLLISION",
+            Code::RewriteUnmatched => "W_PATH_REWRITE",
+            Code::RewriteCollision => "E_PATH_REWRITE_COLLISION",
+            Code::Webhook => "W_WEBHOOK",
+            Code::WebhookRequired => "E_WEBHOOK",
+            Code::Skipped => "I_SKIPPED",
+            Code::Generated => "I_GENERATED",
+            Code::Vendored => "I_VENDORED",
+            Code::AutoExclude => "I_AUTO_EXCLUDE",
+            Code::Excluded => "I_EXCLUDED",
+            Code::ExcludeUnmatched => "W_EXCLUDE_UNMATCHED",
+            Code::FailAbove => "E_FAIL_ABOVE",
+            Code::Gate => "E_GATE",
+            Code::FilesFrom => "W_FILES_FROM",
+            Code::FilesFromUnreadable => "E_FILES_FROM",
+            Code::InvalidUtf8 => "W_INVALID_UTF8",
+            Code::ConfigKey => "W_CONFIG_KEY",
+            Code::Config => "E_CONFIG",
+            Code::Checkpoint => "E_CHECKPOINT",
+            Code::Write => "E_WRITE",
+        }
+    }
+
+    pub fn severity(&self) -> Severity
+    {
+        match self.as_str().as_bytes()[0]
+        {
+            b'E' => Severity::Error,
+            b'W' => Severity::Warning,
+            _ => Severity::Info,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity
+{
+    Info,
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        match self
+        {
+            Severity::Info => write!(f, "info"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// A single error, warning, or note produced during a run. Both the human and
+/// JSON renderings are generated from this so the two can't drift
+#[derive(Debug)]
+pub struct Diagnostic
+{
+    pub code: Code,
+    pub path: Option<PathBuf>,
+    pub message: String,
+}
+
+impl Diagnostic
+{
+    pub fn new(code: Code, path: Option<&Path>, message: impl Into<String>) -> Diagnostic
+    {
+        Diagnostic
+        {
+            code,
+            path: path.map(Path::to_path_buf),
+            message: message.into(),
+        }
+    }
+
+    /// Render as a single JSON object
+    pub fn to_json(&self) -> String
+    {
+        let path = match &self.path
+        {
+            Some(path) => json_string(&display(path)),
+            None => "null".to_string(),
+        };
+
+        format!(r#"{{"code":{},"severity":{},"path":{},"message":{}}}"#,
+                json_string(self.code.as_str()),
+                json_string(&self.code.severity().to_string()),
+                path,
+                json_string(&self.message))
+    }
+}
+
+impl fmt::Display for Diagnostic
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        write!(f, "{}[{}]: ", self.code.severity(), self.code.as_str())?;
+
+        if let Some(path) = &self.path
+        {
+            write!(f, "{}: ", strip_control(&display(path)))?;
+        }
+
+        write!(f, "{}", strip_control(&self.message))
+    }
+}
+
+/// Writes diagnostics to stderr or a file in the chosen format, along with
+/// the progress lines the verbosity asks for and the progress bar. Everything
+/// written to stderr goes around the bar so it is never left half drawn
+pub struct Reporter
+{
+    format: ErrorsFormat,
+    out: Box<dyn Write>,
+    /// Whether `out` is stderr
+    to_stderr: bool,
+    verbosity: Verbosity,
+    progress: Option<ProgressBar>,
+}
+
+impl Reporter
+{
+    pub fn new(format: ErrorsFormat, path: Option<&Path>, verbosity: Verbosity) -> io::Result<Reporter>
+    {
+        let out: Box<dyn Write> = match path
+        {
+            Some(path) => Box::new(io::BufWriter::new(fs::File::create(path)?)),
+            None => Box::new(io::stderr()),
+        };
+
+        Ok(Reporter { format, out, to_stderr: path.is_none(), verbosity, progress: None })
+    }
+
+    /// Write a line to stderr, or to `out` when it is stderr so the order is
+    /// kept, hiding the progress bar while it is written
+    fn stderr_line(&mut self, line: &str)
+    {
+        let out = &mut self.out;
+        let to_stderr = self.to_stderr;
+
+        let mut write = || if to_stderr { writeln!(out, "{}", line) } else { writeln!(io::stderr(), "{}", line) };
+
+        /* if stderr can't be written there is nowhere left to report it */
+        let _ = match &self.progress
+        {
+            Some(bar) => bar.suspend(write),
+            None => write(),
+        };
+    }
+
+    /// Show a progress bar over `total` files, which is only counted if the
+    /// bar will be shown: when stderr is a terminal that isn't quiet or
+    /// taken by JSON diagnostics
+    pub fn start_progress(&mut self, total: impl FnOnce() -> u64)
+    {
+        if self.verbosity == Verbosity::Quiet || !io::stderr().is_terminal() || (self.to_stderr && self.format == ErrorsFormat::Json)
+        {
+            return;
+        }
+
+        let style = ProgressStyle::with_template("{elapsed_precise} [{bar:30}] {pos}/{len} {wide_msg}").unwrap()
+                                                                                                    .progress_chars("=> ");
+
+        self.progress = Some(ProgressBar::new(total()).with_style(style));
+    }
+
+    /// Move the bar on to the file about to be analyzed
+    pub fn advance(&mut self, current: &str)
+    {
+        if let Some(bar) = &self.progress
+        {
+            bar.set_message(strip_control(current));
+            bar.inc(1);
+        }
+    }
+
+    /// Remove the bar so whatever follows starts on a clean line
+    pub fn finish_progress(&mut self)
+    {
+        if let Some(bar) = self.progress.take()
+        {
+            bar.finish_and_clear();
+        }
+    }
+
+    /// JSON always has every diagnostic, as tools filter it themselves. The
+    /// verbosity only decides what people see
+    pub fn emit(&mut self, diagnostic: Diagnostic)
+    {
+        let shown = match diagnostic.code.severity()
+        {
+            Severity::Error => true,
+            Severity::Warning => self.verbosity >= Verbosity::Normal,
+            Severity::Info => self.verbosity >= Verbosity::Debug,
+        };
+
+        let line = match self.format
+        {
+            ErrorsFormat::Human if !shown => return,
+            ErrorsFormat::Human => diagnostic.to_string(),
+            ErrorsFormat::Json => diagnostic.to_json(),
+        };
+
+        if self.to_stderr
+        {
+            self.stderr_line(&line);
+        }
+        else
+        {
+            /* if diagnostics can't be written there is nowhere left to report it */
+            let _ = writeln!(self.out, "{}", line);
+        }
+    }
+
+    /// Write a progress line to stderr if the verbosity is at least `level`.
+    /// Left out when stderr has JSON diagnostics, which it would break up
+    pub fn log(&mut self, level: Verbosity, message: &str)
+    {
+        if self.verbosity < level || (self.to_stderr && self.format == ErrorsFormat::Json)
+        {
+            return;
+        }
+
+        self.stderr_line(&strip_control(message));
+    }
+}
+
+impl Drop for Reporter
+{
+    fn drop(&mut self)
+    {
+        let _ = self.out.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn json_and_human_forms()
+    {
+        let diagnostic = Diagnostic::new(Code::Io, Some(Path::new("src/a\tb.c")), "could not be read: \"denied\"");
+
+        assert_eq!(diagnostic.to_json(), r#"{"code":"E_IO","severity":"error","path":"src/a\tb.c","message":"could not be read: \"denied\""}"#);
+        assert_eq!(diagnostic.to_string(), "error[E_IO]: src/a?b.c: could not be read: \"denied\"");
+
+        let no_path = Diagnostic::new(Code::Unsupported, None, "skipped 1 file");
+
+        assert_eq!(no_path.to_json(), r#"{"code":"W_UNSUPPORTED","severity":"warning","path":null,"message":"skipped 1 file"}"#);
+        assert_eq!(no_path.to_string(), "warning[W_UNSUPPORTED]: skipped 1 file");
+    }
+
+    #[test]
+    fn severity_follows_the_code_prefix()
+    {
+        assert_eq!(Code::Write.severity(), Severity::Error);
+        assert_eq!(Code::ConfigKey.severity(), Severity::Warning);
+        assert_eq!(Code::Skipped.severity(), Severity::Info);
+    }
+
+    #[test]
+    fn json_diagnostics_go_to_the_errors_file_whatever_the_verbosity()
+    {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("errors.jsonl");
+
+        let mut reporter = Reporter::new(ErrorsFormat::Json, Some(&path), Verbosity::Quiet).unwrap();
+        reporter.emit(Diagnostic::new(Code::Skipped, Some(Path::new("a.txt")), "unsupported extension"));
+        reporter.emit(Diagnostic::new(Code::Gate, None, "1 file is above --fail-above 5"));
+        drop(reporter);
+
+        let lines: Vec<serde_json::Value> = fs::read_to_string(&path).unwrap()
+                                                                     .lines()
+                                                                     .map(|line| serde_json::from_str(line).unwrap())
+                                                                     .collect();
+
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["code"], "I_SKIPPED");
+        assert_eq!(lines[1]["severity"], "error");
+    }
+
+    #[test]
+    fn human_diagnostics_are_filtered_by_verbosity()
+    {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("errors.txt");
+
+        let mut reporter = Reporter::new(ErrorsFormat::Human, Some(&path), Verbosity::Normal).unwrap();
+        reporter.emit(Diagnostic::new(Code::Skipped, Some(Path::new("a.txt")), "unsupported extension"));
+        reporter.emit(Diagnostic::new(Code::ConfigKey, None, "unknown key 'colour' was ignored"));
+        reporter.emit(Diagnostic::new(Code::Write, None, "cannot be written"));
+        drop(reporter);
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "warning[W_CONFIG_KEY]: unknown key 'colour' was ignored\nerror[E_WRITE]: cannot be written\n");
+    }
+}
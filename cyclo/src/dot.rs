@@ -0,0 +1,69 @@
+use std::collections::HashMap;
+
+use crate::escape::strip_control;
+use crate::report::{Record, Rollup};
+
+
+/// Fill colors by mean cc, from the same greens as the treemap up to red
+const BUCKETS: [(f64, &str); 4] = [(5.0, "#c7e9c0"), (10.0, "#fdd49e"), (20.0, "#fc8d59"), (f64::INFINITY, "#d7301f")];
+
+/// Escape a string for inside DOT quotes. Backslashes start escapes like `\n`
+/// in labels, so they are doubled along with the quotes
+fn escaped(s: &str) -> String
+{
+    strip_control(s).replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Fill color for a mean cc
+fn fill(cc: f64) -> &'static str
+{
+    BUCKETS.iter().find(|(max, _)| cc <= *max).map(|(_, color)| *color).unwrap_or_default()
+}
+
+/// Levels below the top node, which is 0
+fn depth(record: &Record) -> usize
+{
+    record.label.matches('/').count()
+}
+
+/// Render the hierarchy as a Graphviz digraph, one node per file and
+/// directory, labelled with the name, nloc, and mean cc and filled by how
+/// complex it is. A directory's numbers are over every file below it. Nothing
+/// deeper than `max_depth` levels below the top node is drawn, though it still
+/// counts towards the directories above it
+pub fn render(records: &[Record], max_depth: usize) -> String
+{
+    let mut totals: HashMap<&str, Rollup> = HashMap::new();
+
+    for file in records.iter().filter(|r| !r.is_dir)
+    {
+        totals.entry(&file.label).or_default().add(file);
+
+        for (i, _) in file.label.match_indices('/')
+        {
+            totals.entry(&file.label[..i]).or_default().add(file);
+        }
+    }
+
+    let mut out = String::from("digraph cyclo {\n    node [shape=box, style=filled];\n");
+
+    for record in records.iter().filter(|r| depth(r) <= max_depth)
+    {
+        let total = totals.get(record.label.as_str());
+        let nloc = total.map(|t| t.nloc).unwrap_or(0);
+        let cc = total.map(|t| t.mean_cc()).unwrap_or(0.0);
+        let name = record.label.rsplit('/').next().unwrap_or_default();
+
+        out.push_str(&format!("    \"{}\" [label=\"{}\\nnloc {}\\ncc {:.2}\", fillcolor=\"{}\"];\n",
+                              escaped(&record.label), escaped(name), nloc, cc, fill(cc)));
+
+        if !record.parent.is_empty()
+        {
+            out.push_str(&format!("    \"{}\" -> \"{}\";\n", escaped(&record.parent), escaped(&record.label)));
+        }
+    }
+
+    out.push_str("}\n");
+
+    out
+}
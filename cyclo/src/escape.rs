@@ -0,0 +1,156 @@
+/// Quote and escape a string for JSON. The result is also a valid JS string
+/// literal, including the line separators JSON allows but older JS does not
+pub fn json_string(s: &str) -> String
+{
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+
+    for c in s.chars()
+    {
+        match c
+        {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            '\u{2028}' | '\u{2029}' => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+
+    out.push('"');
+    out
+}
+
+/// Format a list of strings as a JSON/JS array
+pub fn json_string_array(items: &[String]) -> String
+{
+    let items: Vec<String> = items.iter().map(|s| json_string(s)).collect();
+
+    format!("[{}]", items.join(", "))
+}
+
+/// Replace control characters (newlines and tabs are legal in file names) so
+/// text can't break line or tab separated output
+pub fn strip_control(s: &str) -> String
+{
+    s.chars()
+     .map(|c| if c.is_control() { '?' } else { c })
+     .collect()
+}
+
+/// Escape the characters that are special in HTML
+pub fn html(s: &str) -> String
+{
+    s.replace('&', "&amp;")
+     .replace('<', "&lt;")
+     .replace('>', "&gt;")
+     .replace('"', "&quot;")
+}
+
+/// Shorten a label to at most `max` characters by replacing the middle with an
+/// ellipsis, keeping the start and the end (usually the file name) readable
+pub fn middle_ellipsis(s: &str, max: usize) -> String
+{
+    let len = s.chars().count();
+
+    if len <= max || max < 3
+    {
+        return s.to_string();
+    }
+
+    /* one character goes to the ellipsis, the end gets the larger half */
+    let tail = (max - 1).div_ceil(2);
+    let head = max - 1 - tail;
+
+    let start: String = s.chars().take(head).collect();
+    let end: String = s.chars().skip(len - tail).collect();
+
+    format!("{}…{}", start, end)
+}
+
+/// Percent-encode a single URL path segment, leaving only the unreserved
+/// characters as they are
+pub fn url_segment(s: &str) -> String
+{
+    let mut out = String::with_capacity(s.len());
+
+    for b in s.bytes()
+    {
+        match b
+        {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => out.push(b as char),
+            b => out.push_str(&format!("%{:02X}", b)),
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn json_strings_escape_quotes_and_controls()
+    {
+        assert_eq!(json_string("a\"b\\c"), r#""a\"b\\c""#);
+        assert_eq!(json_string("tab\there\nnew\rline"), r#""tab\there\nnew\rline""#);
+        assert_eq!(json_string("bell\u{7}"), r#""bell\u0007""#);
+        assert_eq!(json_string("sep\u{2028}\u{2029}"), r#""sep\u2028\u2029""#);
+        assert_eq!(json_string("ünïcode/ok"), "\"ünïcode/ok\"");
+    }
+
+    #[test]
+    fn json_strings_parse_back()
+    {
+        let name = "we\tird\nname \"quoted\" \\ \u{1b}[31m.c";
+        let parsed: String = serde_json::from_str(&json_string(name)).unwrap();
+
+        assert_eq!(parsed, name);
+        assert_eq!(json_string_array(&[name.to_string(), "b".to_string()]), format!("[{}, \"b\"]", json_string(name)));
+    }
+
+    #[test]
+    fn control_characters_are_stripped()
+    {
+        assert_eq!(strip_control("a\tb\nc\rd\u{1b}e"), "a?b?c?d?e");
+        assert_eq!(strip_control("plain/path.c"), "plain/path.c");
+    }
+
+    #[test]
+    fn html_is_escaped()
+    {
+        assert_eq!(html("<a href=\"x\">&</a>"), "&lt;a href=&quot;x&quot;&gt;&amp;&lt;/a&gt;");
+    }
+
+    #[test]
+    fn long_labels_keep_both_ends()
+    {
+        assert_eq!(middle_ellipsis("src/deeply/nested/module/file.c", 12), "src/d…file.c");
+        assert_eq!(middle_ellipsis("src/deeply/nested/module/file.c", 12).chars().count(), 12);
+        assert_eq!(middle_ellipsis("short.c", 12), "short.c");
+        assert_eq!(middle_ellipsis("exactly.c", 9), "exactly.c");
+        assert_eq!(middle_ellipsis("tiny limit", 2), "tiny limit");
+    }
+
+    #[test]
+    fn long_labels_are_cut_on_characters()
+    {
+        let label = "ディレクトリ/ファイル名.c";
+        let short = middle_ellipsis(label, 7);
+
+        assert_eq!(short, "ディレ…名.c");
+        assert_eq!(short.chars().count(), 7);
+    }
+
+    #[test]
+    fn url_segments_are_percent_encoded()
+    {
+        assert_eq!(url_segment("a b/c~d.e"), "a%20b%2Fc~d.e");
+        assert_eq!(url_segment("é"), "%C3%A9");
+    }
+}
@@ -1,21 +1,69 @@
-use std::io::{BufReader, BufRead};
+use std::cell::Cell;
 use std::option::Option;
 use std::result::Result;
-use std::fs::File;
 use std::vec::Vec;
 use walkdir::DirEntry;
+#[cfg(feature = "tokei")]
 use tokei::{Config, Languages, LanguageType};
+use serde::Serialize;
 use snafu::prelude::*;
 
+use crate::labels::{self, Labeler};
+use crate::source;
 
-/// This error is returned if a file is unabled to be parsed due to an
-/// unknown extension. It should never get to this point as there is
-/// layered parsing, but just in case
+
+/// Errors returned when a file is unable to be parsed
 #[derive(Debug, Snafu)]
 pub enum FileParserError
 {
+    /// The file has an unknown extension. It should never get to this point
+    /// as there is layered parsing, but just in case
     #[snafu(display("The file '{file}' has a bad extension and could not be parsed"))]
     BadFileExtension { file: String },
+    /// The file is not under the analyzed root, e.g. it was reached through a
+    /// symlink, so it can't be given a label
+    #[snafu(display("The file '{file}' is outside of the analyzed root"))]
+    OutsideRoot { file: String },
+    /// The file could not be opened or read
+    #[snafu(display("The file '{file}' could not be read: {source}"))]
+    Unreadable { file: String, source: std::io::Error },
+    /// The file isn't valid UTF-8 and `strict_utf8` is set
+    #[snafu(display("The file '{file}' is not valid UTF-8 and was left out"))]
+    InvalidUtf8 { file: String },
+}
+
+/// Optional counting rules that are off by default to keep results comparable
+/// between runs
+#[derive(Debug, Default, Clone)]
+pub struct ParseOptions
+{
+    /// Count ternaries in file-scope initializers, as if they were in a
+    /// synthetic `<file-scope>` function. C/C++ only
+    pub count_global_init: bool,
+    /// Count compile-time branches (`if constexpr`, `if consteval`, and
+    /// conditional `noexcept`) into `compile_time_cc`. C++ only
+    pub count_compile_time: bool,
+    /// Treat function-like macro definitions as pseudo-functions so the
+    /// branching inside them is attributed to the defining file. C/C++ only
+    pub macros_as_functions: bool,
+    /// Leave out files that aren't valid UTF-8 instead of replacing the
+    /// invalid bytes
+    pub strict_utf8: bool,
+}
+
+/// Name of the pseudo-function holding the branching of file-scope
+/// initializers, see `count_global_init`
+pub const FILE_SCOPE: &str = "<file-scope>";
+
+/// A function-like macro definition, or the file-scope initializers, counted
+/// as a pseudo-function
+#[derive(Debug, Clone, Serialize)]
+pub struct MacroFunction
+{
+    /// `MACRO:` followed by the macro name, or [`FILE_SCOPE`]
+    pub name: String,
+    /// Number of decision statements and logical operations in the body
+    pub cc: u64,
 }
 
 /// Struct representing a valid file to be parsed
@@ -25,10 +73,28 @@ pub struct FileParser<'a>
     pub filename: String,
     /// Raw DirEntry type
     entry: &'a DirEntry,
+    /// Builds the label and parent from the path
+    labeler: &'a Labeler,
+    /// Optional counting rules
+    options: &'a ParseOptions,
     /// Mean function cyclomatic complexity for the file. Used for the Treemap.
     pub cc: Option<f64>,
+    /// Mean function compile-time complexity for the file, kept apart from
+    /// `cc` so runtime complexity stays comparable. Only set when enabled
+    pub compile_time_cc: Option<f64>,
+    /// Function-like macros found when `macros_as_functions` is enabled, and
+    /// the `<file-scope>` function when `count_global_init` is
+    pub macros: Vec<MacroFunction>,
+    /// Macro bodies that couldn't be parsed (unbalanced brackets) and so
+    /// weren't counted
+    pub skipped_macros: u64,
+    /// Estimated number of functions, including pseudo-functions, that the
+    /// complexity is a mean over
+    pub functions: u64,
     /// Number of lines of code for the file. Used for the Treemap.
     pub nloc: Option<u64>,
+    /// Some bytes weren't valid UTF-8 and were replaced before counting
+    pub had_invalid_utf8: bool,
     /// The parent directory that the file is in. Used for the Treemap.
     pub parent: Option<String>,
     /// The path to the file from the root, including flename. Used for the
@@ -38,35 +104,463 @@ pub struct FileParser<'a>
 
 /// Check if the file extension can be parsed by this program. Return TRUE if
 /// it can, FALSE if it cannot.
-/// Currently supported extensions are for C, C++, Python, and Javascript
+/// Currently supported extensions are for C, C++, Python, Javascript,
+/// TypeScript, Rust, Java, Kotlin, Swift, C#, Objective-C, and PHP
 pub fn is_file_extension_valid(file: &str) -> bool
 {
-    let extensions = vec![".c", ".cpp", ".cc", ".cxx", ".py", ".js"];
+    let extensions = [".c", ".cpp", ".cc", ".cxx", ".ixx", ".cppm", ".py", ".js", ".mjs", ".ts", ".tsx", ".rs", ".java", ".kt", ".kts", ".swift", ".cs", ".m", ".mm", ".php"];
 
     extensions.iter()
               .any(|n| file.ends_with(*n))
 }
 
-/// Check if a directory is hidden. Return TRUE if hidden, FALSE if not
-pub fn is_hidden(entry: &DirEntry) -> bool
+/// Get the language of a file from its extension, or an empty string if the
+/// extension is not one that can be parsed
+pub fn file_language(file: &str) -> &'static str
+{
+    /* fragile to multiple extensions but that is such an unlikely edge case */
+    match file.rsplit('.').next().unwrap()
+    {
+        "c" => "c",
+        "cc" => "cpp",
+        "cxx" => "cpp",
+        "cpp" => "cpp",
+        "ixx" => "cpp",
+        "cppm" => "cpp",
+        "py" => "py",
+        "js" => "js",
+        "mjs" => "js",
+        "ts" => "ts",
+        "tsx" => "tsx",
+        "rs" => "rs",
+        "java" => "java",
+        "kt" => "kt",
+        "kts" => "kt",
+        "swift" => "swift",
+        "cs" => "cs",
+        "m" => "objc",
+        "mm" => "objcpp",
+        "php" => "php",
+        _ => ""
+    }
+}
+
+/// Extensions that are parsed but that tokei doesn't recognize, so their nloc
+/// comes from `count_code_lines` instead
+const TOKEI_UNKNOWN_EXTENSIONS: [&str; 2] = [".ixx", ".cppm"];
+
+/// How comments are written in a language, for counting code lines without
+/// tokei
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CommentStyle
+{
+    /// `//` and `/* */`
+    C,
+    /// `#` to the end of the line
+    Hash,
+}
+
+/// Count code lines the way tokei does for C and C++, so the nloc is the same
+/// with or without the tokei feature. A line is a comment when it starts with
+/// `//` or `/*` and ends with `*/`, when it starts inside a block comment, or
+/// when it opens a block comment that is still open at its end. Every other
+/// nonblank line is code, even if only a comment follows the code, and text
+/// inside a string literal never opens or closes a comment
+fn count_code_lines(text: &str, style: CommentStyle) -> u64
+{
+    let mut in_block = false;
+    let mut quote: Option<&str> = None;
+    let mut count = 0;
+
+    for line in text.lines()
+    {
+        let line = line.trim();
+
+        if line.is_empty()
+        {
+            continue;
+        }
+
+        if style == CommentStyle::Hash
+        {
+            if !line.starts_with('#')
+            {
+                count += 1;
+            }
+
+            continue;
+        }
+
+        let started_in_block = in_block;
+        let bytes = line.as_bytes();
+        let mut i = 0;
+
+        while i < bytes.len()
+        {
+            let rest = &bytes[i..];
+
+            /* an escaped quote is skipped everywhere but in a raw string */
+            if let Some(end) = quote.filter(|end| rest.starts_with(end.as_bytes()))
+            {
+                quote = None;
+                i += end.len();
+            }
+            else if rest.starts_with(b"\\\"") && quote != Some(")\"")
+            {
+                i += 2;
+            }
+            else if in_block && rest.starts_with(b"*/")
+            {
+                in_block = false;
+                i += 2;
+            }
+            else if quote.is_some()
+            {
+                i += 1;
+            }
+            else if rest.starts_with(b"/*")
+            {
+                in_block = true;
+                i += 2;
+            }
+            else if in_block
+            {
+                i += 1;
+            }
+            else if rest.starts_with(b"R\"(")
+            {
+                quote = Some(")\"");
+                i += 3;
+            }
+            else if rest.starts_with(b"\"")
+            {
+                quote = Some("\"");
+                i += 1;
+            }
+            else if rest.starts_with(b"//")
+            {
+                break;
+            }
+            else
+            {
+                i += 1;
+            }
+        }
+
+        let comment = quote.is_none() && (line.starts_with("//")
+                                          || (line.starts_with("/*") && line.ends_with("*/"))
+                                          || started_in_block
+                                          || (in_block && line.starts_with("/*")));
+
+        if !comment
+        {
+            count += 1;
+        }
+    }
+
+    count
+}
+
+/// Get the name of the macro if the line starts a function-like macro
+/// definition, e.g. `#define HANDLE_CASE(x) ...`. The parenthesis has to follow
+/// the name directly, otherwise it is an object-like macro
+fn function_like_macro(line: &str) -> Option<&str>
+{
+    let rest = line.trim_start().strip_prefix('#')?.trim_start().strip_prefix("define")?;
+
+    if !rest.starts_with([' ', '\t'])
+    {
+        return None;
+    }
+
+    let rest = rest.trim_start();
+    let end = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))?;
+
+    if end == 0 || !rest[end..].starts_with('(')
+    {
+        return None;
+    }
+
+    Some(&rest[..end])
+}
+
+/// Check if a line has a compile-time branch: `if constexpr`, `if consteval`
+/// or a `noexcept` with a condition rather than a plain `noexcept(true)`
+fn is_compile_time_branch(line: &str) -> bool
+{
+    if line.contains("if constexpr") || line.contains("if consteval") || line.contains("if !consteval")
+    {
+        return true;
+    }
+
+    line.match_indices("noexcept(")
+        .any(|(i, m)| {
+            let rest = line[i + m.len()..].trim_start();
+            !(rest.starts_with(')') || rest.starts_with("true)") || rest.starts_with("false)"))
+        })
+}
+
+/// How function definitions are recognized in a language
+#[derive(Debug, Clone, Copy)]
+struct FunctionDef
+{
+    /// Any line containing one of these keywords, e.g. `def`
+    keywords: &'static [&'static str],
+    /// Also lines that look like a method signature, for languages where
+    /// methods have no keyword
+    signatures: bool,
+}
+
+/// Check if a line looks like a method or constructor signature, e.g.
+/// `public static <T> List<T> sorted(List<T> items) {`, as opposed to a call,
+/// a control statement, or `new Runnable() {`
+fn is_method_signature(line: &str) -> bool
 {
-    entry.file_name()
-         .to_str()
-         .map(|s| s.starts_with("."))
-         .unwrap_or(false)
+    /* `await f(x)` and the like are calls even without a semicolon */
+    const NOT_NAMES: [&str; 19] = ["if", "for", "foreach", "while", "switch", "catch", "synchronized",
+                                   "lock", "using", "fixed", "return", "new", "throw", "else", "do", "try",
+                                   "await", "yield", "typeof"];
+
+    let line = line.trim();
+
+    if line.starts_with('@') || line.ends_with(';')
+    {
+        return false;
+    }
+
+    let head: Vec<&str> = match line.find('(')
+    {
+        Some(open) => line[..open].split_whitespace().collect(),
+        None => return false,
+    };
+
+    let name = match head.last()
+    {
+        Some(name) => *name,
+        None => return false,
+    };
+
+    if !name.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '$')
+       || head.iter().any(|token| NOT_NAMES.contains(token) || token.contains('='))
+    {
+        return false;
+    }
+
+    /* a lone name is only a constructor if its body starts on the same line,
+     * otherwise it's more likely a call split over several lines */
+    head.len() >= 2 || line.ends_with('{')
 }
 
+/// The `for`s in a Rust line that aren't loops: the one in
+/// `impl Trait for Type`, and `for<'a>` lifetime bounds
+fn non_loop_fors(line: &str) -> usize
+{
+    let line = line.trim_start();
+    let line = line.strip_prefix("unsafe ").unwrap_or(line);
+
+    if line.starts_with("impl ") || line.starts_with("impl<")
+    {
+        return count_word(line, "for");
+    }
+
+    count_word(line, "for<")
+}
+
+/// Check if a line is a branch of a Kotlin `when`, e.g. `is Error -> {`, as
+/// opposed to the `else` branch or a lambda like `{ x -> x * 2 }`. Only lines
+/// directly inside a `when` are asked, as `->` is also in function types
+fn is_when_entry(line: &str) -> bool
+{
+    let line = line.trim();
+
+    match line.find("->")
+    {
+        Some(arrow) => !line[..arrow].contains('{') && !line.starts_with("else"),
+        None => false,
+    }
+}
+
+/// The parts of a line inside `<?php ... ?>` tags, so that the HTML around
+/// them in a template isn't searched for keywords. `in_php` carries whether a
+/// block is still open from the previous line
+fn php_code(line: &str, in_php: &mut bool) -> String
+{
+    let mut code = String::new();
+    let mut rest = line;
+
+    loop
+    {
+        if *in_php
+        {
+            match rest.find("?>")
+            {
+                Some(end) => {
+                    code.push_str(&rest[..end]);
+                    code.push(' ');
+                    rest = &rest[end + 2..];
+                    *in_php = false;
+                },
+                None => {
+                    code.push_str(rest);
+                    return code;
+                }
+            }
+        }
+        else
+        {
+            /* `<?php`, the short echo tag `<?=`, and the bare `<?` */
+            match rest.find("<?")
+            {
+                Some(start) => {
+                    rest = &rest[start + 2..];
+                    rest = rest.strip_prefix("php").or_else(|| rest.strip_prefix('=')).unwrap_or(rest);
+                    *in_php = true;
+                },
+                None => return code,
+            }
+        }
+    }
+}
+
+/// Follows the blocks opened by a keyword, e.g. the body of each Kotlin
+/// `when`, so a line can be known to be directly inside one rather than in
+/// something nested in it
+struct Blocks
+{
+    /// Where the keyword ends in a line, if the line has it
+    opener: fn(&str) -> Option<usize>,
+    /// Brace depth at the start of the next line
+    depth: i64,
+    /// The depth inside each open block, innermost last
+    open: Vec<i64>,
+    /// The last line had the keyword but not the brace of its block
+    pending: bool,
+}
+
+impl Blocks
+{
+    fn new(opener: fn(&str) -> Option<usize>) -> Blocks
+    {
+        Blocks { opener, depth: 0, open: Vec::new(), pending: false }
+    }
+
+    /// Move past a line, returning whether it starts directly inside a block.
+    /// Every line has to be seen, comment lines too, to keep the depth right
+    fn next_line(&mut self, line: &str) -> bool
+    {
+        let code = line.split("//").next().unwrap_or(line);
+        let inside = self.open.last() == Some(&self.depth);
+
+        /* the first brace after the keyword opens its block, or the first on
+         * the line after when the brace was put on its own line */
+        let keyword = (self.opener)(code);
+        let mut start = keyword.or(if self.pending { Some(0) } else { None });
+
+        for (i, c) in code.char_indices()
+        {
+            match c
+            {
+                '{' => {
+                    self.depth += 1;
+
+                    if start.is_some_and(|start| i >= start)
+                    {
+                        self.open.push(self.depth);
+                        start = None;
+                    }
+                },
+                '}' => {
+                    self.depth = (self.depth - 1).max(0);
+
+                    while self.open.last().is_some_and(|open| *open > self.depth)
+                    {
+                        self.open.pop();
+                    }
+                },
+                _ => {},
+            }
+        }
+
+        self.pending = keyword.is_some() && start.is_some();
+        inside
+    }
+}
+
+/// The start of each time a keyword appears in a line as a whole word
+fn find_words<'a>(line: &'a str, word: &'a str) -> impl Iterator<Item = usize> + 'a
+{
+    let is_ident = |c: char| c.is_alphanumeric() || c == '_';
+
+    /* only ends of the word that are part of an identifier need a boundary */
+    let check_before = word.chars().next().is_some_and(is_ident);
+    let check_after = word.chars().next_back().is_some_and(is_ident);
+
+    line.match_indices(word)
+        .filter(move |(i, m)| {
+            let before = line[..*i].chars().next_back();
+            let after = line[i + m.len()..].chars().next();
+
+            let joined_before = check_before && before.is_some_and(is_ident);
+            let joined_after = check_after && after.is_some_and(is_ident);
+
+            !joined_before && !joined_after
+        })
+        .map(|(i, _)| i)
+}
+
+/// Count the times a keyword appears in a line as a whole word, so that `if`
+/// is found in `} else if x {` but not in `notify()`
+fn count_word(line: &str, word: &str) -> usize
+{
+    find_words(line, word).count()
+}
+
+/// Where the first whole-word `when` in a line ends
+fn kotlin_when(line: &str) -> Option<usize>
+{
+    find_words(line, "when").next().map(|i| i + "when".len())
+}
+
+/// Where the first whole-word `switch` in a line ends
+fn swift_switch(line: &str) -> Option<usize>
+{
+    find_words(line, "switch").next().map(|i| i + "switch".len())
+}
+
+/// Where the `switch` of a C# switch expression ends in a line, e.g.
+/// `var name = shape switch {`, as opposed to a `switch (x)` statement
+fn csharp_switch_expression(line: &str) -> Option<usize>
+{
+    find_words(line, "switch").map(|i| i + "switch".len())
+                              .find(|end| !line[*end..].trim_start().starts_with('('))
+}
+
+/// The `case`s in a Swift line outside of a `switch`, which are enum cases or
+/// patterns in `if case` and the like rather than branches
+fn non_switch_cases(line: &str, in_switch: bool) -> usize
+{
+    if in_switch { 0 } else { count_word(line, "case") }
+}
+
+
 
 impl<'a> FileParser<'_>
 {
-    pub fn new (entry: &'a DirEntry) -> FileParser<'a>
+    pub fn new (entry: &'a DirEntry, labeler: &'a Labeler, options: &'a ParseOptions) -> FileParser<'a>
     {
         FileParser
         {
             filename: entry.file_name().to_os_string().into_string().unwrap(),
-            entry: entry,
+            entry,
+            labeler,
+            options,
             cc: None,
+            compile_time_cc: None,
+            macros: Vec::new(),
+            skipped_macros: 0,
+            functions: 0,
             nloc: None,
+            had_invalid_utf8: false,
             parent: None,
             label: None
         }
@@ -79,16 +573,7 @@ impl<'a> FileParser<'_>
     pub fn file_walk(&mut self) -> Result<(), FileParserError>
     {
         /* first get the mean of function complexities for the file */
-        match self.get_file_complexity()
-        {
-            Some(complexity) => self.cc = Some(complexity),
-            _ => {
-                return BadFileExtensionSnafu
-                {
-                    file: &self.filename,
-                }.fail()
-            }
-        }
+        self.cc = Some(self.get_file_complexity()?);
 
         /* then get the nloc for the file */
         match self.get_file_nloc()
@@ -103,39 +588,47 @@ impl<'a> FileParser<'_>
         }
 
         /* finally set the values as vec elements for the treemap */
-        let depth = self.entry.depth();
-
-        let len = self.entry.path().to_str().unwrap()
-                                   .split("/").count();
+        self.set_label()
+    }
 
-        let mut full_path = self.entry.path().to_str().unwrap()
-                                  .split("/")
-                                  .collect::<Vec<&str>>();
+    /// Take the results of an earlier run instead of parsing the file again,
+    /// see `--resume`
+    pub fn resume(&mut self, nloc: u64, cc: f64, compile_time_cc: Option<f64>, functions: u64, macros: Vec<MacroFunction>, had_invalid_utf8: bool) -> Result<(), FileParserError>
+    {
+        self.nloc = Some(nloc);
+        self.had_invalid_utf8 = had_invalid_utf8;
+        self.cc = Some(cc);
+        self.compile_time_cc = compile_time_cc;
+        self.functions = functions;
+        self.macros = macros;
 
-        /* the label is /path/to/file.c */
-        self.label = Some(full_path[len-depth-1..].join("/"));
+        self.set_label()
+    }
 
-        full_path.pop();
+    /// Set the label and parent from the path
+    fn set_label(&mut self) -> Result<(), FileParserError>
+    {
+        let label = match self.labeler.label(self.entry.path())
+        {
+            Some(label) => label,
+            None => {
+                return OutsideRootSnafu
+                {
+                    file: self.entry.path().display().to_string(),
+                }.fail()
+            }
+        };
 
-        /* the parent is /path/to */
-        self.parent = Some(full_path[len-depth-1..].join("/"));
+        /* the label is path/to/file.c and the parent is path/to */
+        self.parent = Some(labels::parent_label(&label).to_string());
+        self.label = Some(label);
         Ok(())
     }
 
     /// Get the file extension given a file name
-    fn get_file_extension(&mut self) -> &str
+    fn get_file_extension(&self) -> &'static str
     {
-        /* fragile to multiple extensions but that is such an unlikely edge case */
-        match self.filename.as_str().rsplit(".").next().unwrap()
-        {
-            "c" => "c",
-            "cc" => "cpp",
-            "cxx" => "cpp",
-            "cpp" => "cpp",
-            "py" => "py",
-            "js" => "js",
-            _ => ""
-        }
+        file_language(&self.filename)
     }
 
     /// Get the mean function complexity in a file by manually searching for
@@ -147,52 +640,175 @@ impl<'a> FileParser<'_>
     /// tree-sitter is awesome but was very fragile when dealing with
     /// C/C++ preprocessor directives. doing it the below way is simpler and
     /// returns a reasonable approximation of the actual cyclomatic complexity.
-    fn get_file_complexity(&mut self) -> Option<f64>
+    fn get_file_complexity(&mut self) -> Result<f64, FileParserError>
     {
         let mut comments: Vec<&str> = Vec::new();
         let mut statements: Vec<&str> = Vec::new();
         let mut logical_ops: Vec<&str> = Vec::new();
-        let function_def: &str;
+        let mut global_init = false;
+        let mut compile_time = false;
+        let mut macros_as_functions = false;
+        /* keywords have to be whole words rather than any substring, and each
+         * one on a line is a decision, e.g. `[x for x in xs if x]` has two */
+        let mut whole_words = false;
+        /* the body lines of `/** ... */` comments start with `*` */
+        let mut starred_comments = false;
+        /* Kotlin `when` branches are decisions without a keyword */
+        let mut when_entries = false;
+        /* the blocks whose direct lines are told apart, e.g. `when` bodies */
+        let mut blocks: Option<Blocks> = None;
+        /* the lines directly inside `blocks` are arms, not functions */
+        let mut arms = false;
+        /* PHP files can be HTML templates with code in between tags */
+        let mut php_blocks = false;
+        /* keywords that aren't decisions where they are used, given whether
+         * the line is directly inside one of `blocks` */
+        let mut false_decisions: fn(&str, bool) -> usize = |_, _| 0;
 
         /* identify the extension */
-        match self.get_file_extension()
+        let function_def = match self.get_file_extension()
         {
-            "c" => {
+            /* Objective-C methods return like C functions do */
+            "c" | "objc" => {
                 comments.extend(["//", "/*", "*/", "*", "///"].iter());
                 statements.extend(["if(", "if (", "for(", "for (", "while(", "while (", "switch", "break", "goto"].iter());
                 logical_ops.extend(["&&", "||"].iter());
-                function_def = "return";
-
+                global_init = self.options.count_global_init;
+                macros_as_functions = self.options.macros_as_functions;
+                FunctionDef { keywords: &["return"], signatures: false }
             },
-            "cpp" => {
+            "cpp" | "objcpp" => {
                 comments.extend(["//", "/*", "*/", "*", "///"].iter());
                 statements.extend(["if(", "if (", "for(", "for (", "while(", "while (", "switch", "break", "goto"].iter());
                 logical_ops.extend(["&&", "||"].iter());
-                function_def = "return";
+                global_init = self.options.count_global_init;
+                compile_time = self.options.count_compile_time;
+                macros_as_functions = self.options.macros_as_functions;
+                FunctionDef { keywords: &["return"], signatures: false }
             },
             "py" => {
-                /* TODO */
                 comments.extend(["#"].iter());
-                statements.extend(["if", "for", "while", "break"].iter());
-                logical_ops.extend(["and", "or", "not"].iter());
-                function_def = "def ";
+                statements.extend(["if", "elif", "for", "while", "except"].iter());
+                logical_ops.extend(["and", "or"].iter());
+                whole_words = true;
+                FunctionDef { keywords: &["def"], signatures: false }
             },
-            "js" => {
-                /* TODO */
+            "js" | "ts" | "tsx" => {
                 comments.extend(["//", "*/", "/*"].iter());
-                statements.extend(["if", "for", "while"].iter());
+                statements.extend(["if", "for", "while", "case", "catch"].iter());
+                logical_ops.extend(["&&", "||", "??", " ? "].iter());
+                whole_words = true;
+                starred_comments = true;
+                /* arrow functions and class methods have no function keyword */
+                FunctionDef { keywords: &["function", "=>"], signatures: true }
+            },
+            "rs" => {
+                comments.extend(["//", "/*", "*/", "///", "//!"].iter());
+                statements.extend(["if", "match", "while", "for", "loop"].iter());
                 logical_ops.extend(["&&", "||"].iter());
-                function_def = "function";
+                whole_words = true;
+                false_decisions = |line, _| non_loop_fors(line);
+                FunctionDef { keywords: &["fn"], signatures: false }
             },
-            _ => { return None; },
-        }
+            "java" => {
+                comments.extend(["//", "/*", "*/"].iter());
+                statements.extend(["if", "for", "while", "case", "catch"].iter());
+                /* spaced so that `<?>` wildcards aren't taken for ternaries */
+                logical_ops.extend(["&&", "||", " ? "].iter());
+                whole_words = true;
+                starred_comments = true;
+                FunctionDef { keywords: &[], signatures: true }
+            },
+            "kt" => {
+                comments.extend(["//", "/*", "*/"].iter());
+                statements.extend(["if", "for", "while", "catch"].iter());
+                logical_ops.extend(["&&", "||", "?:"].iter());
+                whole_words = true;
+                starred_comments = true;
+                when_entries = true;
+                blocks = Some(Blocks::new(kotlin_when));
+                FunctionDef { keywords: &["fun"], signatures: false }
+            },
+            "swift" => {
+                comments.extend(["//", "/*", "*/"].iter());
+                /* `repeat { } while x` is counted by its `while` */
+                statements.extend(["if", "guard", "for", "while", "case", "catch"].iter());
+                logical_ops.extend(["&&", "||", "??", " ? "].iter());
+                whole_words = true;
+                starred_comments = true;
+                blocks = Some(Blocks::new(swift_switch));
+                false_decisions = non_switch_cases;
+                FunctionDef { keywords: &["func"], signatures: false }
+            },
+            "php" => {
+                comments.extend(["//", "/*", "*/", "#"].iter());
+                statements.extend(["if", "elseif", "for", "foreach", "while", "case", "catch"].iter());
+                logical_ops.extend(["&&", "||", "??", " ? "].iter());
+                whole_words = true;
+                starred_comments = true;
+                php_blocks = true;
+                /* `fn` is an arrow function */
+                FunctionDef { keywords: &["function", "fn"], signatures: false }
+            },
+            "cs" => {
+                comments.extend(["//", "/*", "*/"].iter());
+                statements.extend(["if", "for", "foreach", "while", "case", "catch"].iter());
+                logical_ops.extend(["&&", "||", "??", " ? "].iter());
+                whole_words = true;
+                starred_comments = true;
+                blocks = Some(Blocks::new(csharp_switch_expression));
+                arms = true;
+                /* lambdas and expression-bodied members */
+                FunctionDef { keywords: &["=>"], signatures: true }
+            },
+            _ => {
+                return BadFileExtensionSnafu
+                {
+                    file: &self.filename,
+                }.fail()
+            },
+        };
 
         let mut logical_ops_count: u64 = 0;
         let mut function_count: u64 = 0;
 
-        let path = self.entry.path();
-        let f = File::open(&path).unwrap();
-        let reader = BufReader::new(f).lines();
+        /* state for finding file-scope initializers. depth is tracked on every
+         * line, as the comment filter drops e.g. `char *f(void) {` for its `*` */
+        let mut depth: i64 = 0;
+        let mut in_global_init = false;
+        let init_line = Cell::new(false);
+        let mut global_init_count: u64 = 0;
+        let mut global_init_cc: u64 = 0;
+        let mut global_init_seen = false;
+
+        let mut compile_time_count: u64 = 0;
+
+        /* the macro being read, and its bracket balance so far */
+        let mut current_macro: Option<(MacroFunction, i64)> = None;
+        let mut macros: Vec<MacroFunction> = Vec::new();
+        let mut skipped_macros: u64 = 0;
+
+        /* whether the line being searched is directly inside one of `blocks`.
+         * blocks see every line, so this is set before comments are dropped */
+        let in_block = Cell::new(false);
+
+        let matches = |line: &str, keyword: &str| if whole_words { count_word(line, keyword) > 0 } else { line.contains(keyword) };
+        let decisions = |line: &str| statements.iter().map(|n| count_word(line, n)).sum::<usize>() - false_decisions(line, in_block.get());
+
+        /* decisions beyond the first on a line, which only counts once */
+        let mut extra_decisions: u64 = 0;
+
+        let source = source::read(self.entry.path()).context(UnreadableSnafu { file: &self.filename })?;
+
+        if source.had_invalid_utf8 && self.options.strict_utf8
+        {
+            return InvalidUtf8Snafu
+            {
+                file: &self.filename,
+            }.fail()
+        }
+
+        self.had_invalid_utf8 = source.had_invalid_utf8;
 
         /* this is how the iterator works:
          * - nukes any comment lines because it might fuck with the keyword searching
@@ -200,45 +816,200 @@ impl<'a> FileParser<'_>
          * - check for a function definition (this is very guess-y). for C/C++ it counts
          * the number of returns. some functions may have more than one, and some functions
          * may have none. hopefully it evens out.
+         * - if enabled, track brace depth so that ternaries and logical operations in
+         * file-scope initializers can be attributed to a synthetic function
+         * - for Kotlin, count the branches directly inside a `when`, and for Swift
+         * only count a `case` directly inside a `switch`. C# switch expression arms
+         * use `=>` like lambdas but aren't functions
+         * - if enabled, count compile-time branches separately from the rest
+         * - if enabled, follow function-like macros across continuation lines,
+         * tallying the decisions in each as its own pseudo-function
          * - search for keywords (language specific) and nuke lines that don't have em
          * - collect it all into a vec. the size is the number of keywords
          * - add to this the number of logical operations counted
          * - done */
 
-        let valid_lines: Vec<String> = reader.map(|x| x.unwrap())
+        let mut in_php = false;
+
+        let valid_lines: Vec<String> = source.text.lines()
+                                    .map(str::to_string)
+                                    .map(|x| if php_blocks { php_code(&x, &mut in_php) } else { x })
+                                    .inspect(|x| in_block.set(blocks.as_mut().is_some_and(|b| b.next_line(x))))
+                                    .inspect(|x| {
+                                        if !global_init
+                                        {
+                                            return;
+                                        }
+
+                                        /* a declaration at depth 0 with an assignment before
+                                         * any parenthesis, e.g. `static int t[] = { ... };` */
+                                        if depth == 0 && !x.trim_start().starts_with('#')
+                                        {
+                                            let eq = x.find('=');
+                                            let paren = x.find('(');
+
+                                            if eq.is_some() && (paren.is_none() || eq < paren)
+                                            {
+                                                in_global_init = true;
+                                            }
+                                        }
+
+                                        init_line.set(in_global_init);
+
+                                        depth += x.matches('{').count() as i64;
+                                        depth -= x.matches('}').count() as i64;
+
+                                        if depth <= 0 && x.contains(';')
+                                        {
+                                            depth = 0;
+                                            in_global_init = false;
+                                        }
+                                    })
                                     .filter(|x| comments.iter().all(|n| !x.contains(*n)))
+                                    .filter(|x| !(starred_comments && x.trim_start().starts_with('*')))
                                     .inspect(|x| {
                                         /* estimating number of logical operations */
                                         for item in &logical_ops
                                         {
-                                            logical_ops_count += if x.contains(item) { 1 } else { 0 };
+                                            logical_ops_count += if matches(x, item) { 1 } else { 0 };
                                         }
 
                                         /* estimating number of functions */
-                                        function_count += if x.contains(function_def) { 1 } else { 0 };
+                                        let is_function = !(arms && in_block.get())
+                                                          && (function_def.keywords.iter().any(|n| matches(x, n))
+                                                              || (function_def.signatures && is_method_signature(x)));
+                                        function_count += if is_function { 1 } else { 0 };
+
+                                        if whole_words
+                                        {
+                                            extra_decisions += decisions(x).saturating_sub(1) as u64;
+                                        }
+
+                                        if when_entries && in_block.get() && is_when_entry(x)
+                                        {
+                                            extra_decisions += 1;
+                                        }
+
+                                        if compile_time && is_compile_time_branch(x)
+                                        {
+                                            compile_time_count += 1;
+                                        }
+
+                                        if macros_as_functions
+                                        {
+                                            if current_macro.is_none()
+                                            {
+                                                current_macro = function_like_macro(x).map(|name| {
+                                                    (MacroFunction { name: format!("MACRO:{}", name), cc: 0 }, 0)
+                                                });
+                                            }
+
+                                            if let Some((m, balance)) = &mut current_macro
+                                            {
+                                                m.cc += if statements.iter().any(|n| x.contains(*n)) { 1 } else { 0 };
+                                                m.cc += logical_ops.iter().filter(|n| x.contains(*n)).count() as u64;
+
+                                                *balance += x.matches(['(', '{']).count() as i64;
+                                                *balance -= x.matches([')', '}']).count() as i64;
+                                            }
+
+                                            /* a line without a continuation ends the macro */
+                                            if !x.trim_end().ends_with('\\')
+                                            {
+                                                match current_macro.take()
+                                                {
+                                                    Some((m, 0)) => macros.push(m),
+                                                    Some(_) => skipped_macros += 1,
+                                                    None => {}
+                                                }
+                                            }
+                                        }
+
+                                        if global_init && init_line.get()
+                                        {
+                                            let ternaries = x.matches('?').count() as u64;
+                                            let logical = logical_ops.iter().filter(|n| x.contains(*n)).count() as u64;
+
+                                            global_init_count += ternaries;
+                                            global_init_cc += ternaries + logical;
+                                            global_init_seen |= ternaries + logical > 0;
+                                        }
                                         })
-                                    .filter(|s| statements.iter().any(|n| s.contains(*n)))
+                                    .filter(|s| if whole_words { decisions(s) > 0 } else { statements.iter().any(|n| s.contains(*n)) })
                                     .collect();
 
         let mut complexity_count: u64 = valid_lines.len().try_into().unwrap();
         complexity_count += logical_ops_count;
+        complexity_count += extra_decisions;
+        complexity_count += global_init_count;
+
+        /* a macro still open at the end of the file was cut off */
+        if current_macro.is_some()
+        {
+            skipped_macros += 1;
+        }
+
+        /* the synthetic <file-scope> function, listed with the macros */
+        if global_init_seen
+        {
+            macros.push(MacroFunction { name: FILE_SCOPE.to_string(), cc: global_init_cc });
+        }
+
+        /* each pseudo-function's decisions were already counted */
+        function_count += macros.len() as u64;
+        self.macros = macros;
+        self.skipped_macros = skipped_macros;
 
-        let mean_complexity: f64;
+        self.functions = function_count;
 
-        if function_count == 0
+        let mean_complexity = if function_count == 0
         {
-            mean_complexity = 0.0;
+            0.0
         }
         else
         {
-            mean_complexity = complexity_count as f64 / function_count as f64;
+            complexity_count as f64 / function_count as f64
+        };
+
+        if compile_time
+        {
+            self.compile_time_cc = Some(if function_count == 0
+            {
+                0.0
+            }
+            else
+            {
+                compile_time_count as f64 / function_count as f64
+            });
         }
 
-        return Some(mean_complexity);
+        Ok(mean_complexity)
     }
 
     /// Get the number of lines of code in a file
     fn get_file_nloc(&mut self) -> Option<u64>
+    {
+        /* without tokei every language goes through the fallback counter, as
+         * do files tokei would count as empty because they aren't valid UTF-8 */
+        if !cfg!(feature = "tokei") || self.had_invalid_utf8 || TOKEI_UNKNOWN_EXTENSIONS.iter().any(|n| self.filename.ends_with(n))
+        {
+            let style = match self.get_file_extension()
+            {
+                "py" => CommentStyle::Hash,
+                _ => CommentStyle::C,
+            };
+
+            let source = source::read(self.entry.path()).ok()?;
+
+            return Some(count_code_lines(&source.text, style));
+        }
+
+        self.get_tokei_nloc()
+    }
+
+    /// Count code lines with tokei
+    #[cfg(feature = "tokei")]
+    fn get_tokei_nloc(&self) -> Option<u64>
     {
         let path = &[self.entry.path().to_str().unwrap()];
         let excluded = &[];
@@ -267,7 +1038,307 @@ impl<'a> FileParser<'_>
                 let lang = &languages[&LanguageType::JavaScript];
                 Some(lang.code.try_into().unwrap())
             },
+            "rs" => {
+                let lang = &languages[&LanguageType::Rust];
+                Some(lang.code.try_into().unwrap())
+            },
+            "ts" => {
+                let lang = &languages[&LanguageType::TypeScript];
+                Some(lang.code.try_into().unwrap())
+            },
+            "tsx" => {
+                let lang = &languages[&LanguageType::Tsx];
+                Some(lang.code.try_into().unwrap())
+            },
+            "java" => {
+                let lang = &languages[&LanguageType::Java];
+                Some(lang.code.try_into().unwrap())
+            },
+            "kt" => {
+                let lang = &languages[&LanguageType::Kotlin];
+                Some(lang.code.try_into().unwrap())
+            },
+            "swift" => {
+                let lang = &languages[&LanguageType::Swift];
+                Some(lang.code.try_into().unwrap())
+            },
+            "php" => {
+                let lang = &languages[&LanguageType::Php];
+                Some(lang.code.try_into().unwrap())
+            },
+            "cs" => {
+                let lang = &languages[&LanguageType::CSharp];
+                Some(lang.code.try_into().unwrap())
+            },
+            "objc" => {
+                let lang = &languages[&LanguageType::ObjectiveC];
+                Some(lang.code.try_into().unwrap())
+            },
+            "objcpp" => {
+                let lang = &languages[&LanguageType::ObjectiveCpp];
+                Some(lang.code.try_into().unwrap())
+            },
             _ => None,
         }
     }
+
+    #[cfg(not(feature = "tokei"))]
+    fn get_tokei_nloc(&self) -> Option<u64>
+    {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use std::fs;
+
+    /// Parse `text` as a file named `name`, returning its function count and
+    /// the total of its decisions
+    fn parse(name: &str, text: &str) -> (u64, f64)
+    {
+        let (functions, cc, _) = parse_with(name, text, &ParseOptions::default());
+        (functions, cc)
+    }
+
+    /// Like `parse`, with the pseudo-functions too
+    fn parse_with(name: &str, text: &str, options: &ParseOptions) -> (u64, f64, Vec<MacroFunction>)
+    {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join(name), text).unwrap();
+
+        let entry = walkdir::WalkDir::new(dir.path().join(name)).into_iter().next().unwrap().unwrap();
+        let labeler = Labeler::new(dir.path(), None);
+        let mut parser = FileParser::new(&entry, &labeler, options);
+
+        let cc = parser.get_file_complexity().unwrap();
+        (parser.functions, cc * parser.functions as f64, parser.macros)
+    }
+
+    #[test]
+    fn signatures_are_methods()
+    {
+        assert!(is_method_signature("public static <T> List<T> sorted(List<T> items) {"));
+        assert!(is_method_signature("async load(url) {"));
+        assert!(is_method_signature("Widget(int size) {"));
+    }
+
+    #[test]
+    fn statements_are_not_methods()
+    {
+        assert!(!is_method_signature("if (x) {"));
+        assert!(!is_method_signature("return compute(x)"));
+        assert!(!is_method_signature("foo(x);"));
+        assert!(!is_method_signature("const y = foo(x)"));
+    }
+
+    #[test]
+    fn javascript_calls_without_semicolons_are_not_methods()
+    {
+        assert!(!is_method_signature("await foo(x)"));
+        assert!(!is_method_signature("yield bar()"));
+        assert!(!is_method_signature("typeof f(x)"));
+        assert!(!is_method_signature("  await this.save(record)"));
+    }
+
+    #[test]
+    fn typescript_awaits_are_calls()
+    {
+        let text = "\
+export async function load(url: string): Promise<Data> {
+    const response = await fetch(url)
+    await save(response)
+    return response.json() as Promise<Data>
+}
+
+function* ids(): Generator<number> {
+    yield next()
+}
+
+if (typeof check(x) === \"string\") {
+}
+";
+
+        assert_eq!(parse("load.ts", text), (2, 1.0));
+        assert_eq!(parse("view.tsx", text), (2, 1.0));
+    }
+
+    #[test]
+    fn rust_impl_for_is_not_a_loop()
+    {
+        assert_eq!(non_loop_fors("impl Display for Threshold {"), 1);
+        assert_eq!(non_loop_fors("unsafe impl<T: Send> Send for Queue<T> {}"), 1);
+        assert_eq!(non_loop_fors("    where F: for<'a> Fn(&'a str) -> bool,"), 1);
+        assert_eq!(non_loop_fors("for item in items {"), 0);
+        assert_eq!(non_loop_fors("let imp = format(x); for y in z {"), 0);
+
+        let text = "\
+impl Iterator for Walk {
+    fn next(&mut self) -> Option<Entry> {
+        for entry in &self.entries {
+            if entry.is_dir() {
+                return Some(entry.clone());
+            }
+        }
+        None
+    }
+}
+
+fn apply<F>(f: F) where F: for<'a> Fn(&'a str) -> bool {
+}
+";
+
+        assert_eq!(parse("walk.rs", text), (2, 2.0));
+    }
+
+    #[test]
+    fn kotlin_function_types_are_not_when_branches()
+    {
+        let text = "\
+val square: (Int) -> Int = { it * it }
+
+fun describe(result: Result, retry: (Int) -> Unit): String {
+    val handler: suspend (String) -> Boolean = ::check
+    return when (result) {
+        is Ok -> \"ok\"
+        is Error -> {
+            items.map { x -> x * 2 }
+            \"error\"
+        }
+        else -> \"unknown\"
+    }
+}
+
+fun pick(x: Int) = when
+{
+    x < 0 -> \"negative\"
+    else -> \"positive\"
+}
+";
+
+        assert_eq!(parse("describe.kt", text), (2, 3.0));
+    }
+
+    #[test]
+    fn swift_enum_cases_are_not_branches()
+    {
+        let text = "\
+enum Direction {
+    case north, south
+    case other(String)
+}
+
+func name(of direction: Direction) -> String {
+    if case .other(let name) = direction {
+        return name
+    }
+    switch direction {
+    case .north:
+        return \"N\"
+    case .south: return \"S\"
+    default:
+        return \"?\"
+    }
+}
+";
+
+        assert_eq!(parse("direction.swift", text), (1, 3.0));
+    }
+
+    #[test]
+    fn csharp_switch_expression_arms_are_not_functions()
+    {
+        assert_eq!(csharp_switch_expression("var area = shape switch {"), Some("var area = shape switch".len()));
+        assert_eq!(csharp_switch_expression("switch (shape)"), None);
+
+        let text = "\
+public class Shapes
+{
+    public double Area(Shape shape) => shape switch
+    {
+        Circle c => Math.PI * c.Radius * c.Radius,
+        Square s => s.Side * s.Side,
+        _ => 0,
+    };
+
+    public string Name(Shape shape)
+    {
+        var names = shapes.Select(s => s.Name);
+        switch (shape)
+        {
+            case Circle:
+                return \"circle\";
+        }
+        return shape switch {
+            Square => \"square\",
+            _ => \"other\",
+        };
+    }
+}
+";
+
+        assert_eq!(parse("Shapes.cs", text), (3, 1.0));
+    }
+
+    #[test]
+    fn pointer_functions_are_not_file_scope()
+    {
+        let options = ParseOptions { count_global_init: true, ..ParseOptions::default() };
+        let text = "char *pick(struct dev *d) {\n    int x = d->on ? 1 : 2;\n    return 0;\n}\n";
+
+        let (functions, cc, pseudo) = parse_with("pick.c", text, &options);
+
+        assert_eq!((functions, cc), (1, 0.0));
+        assert!(pseudo.is_empty(), "{:?}", pseudo);
+    }
+
+    #[test]
+    fn file_scope_initializers_are_a_function()
+    {
+        let options = ParseOptions { count_global_init: true, ..ParseOptions::default() };
+        let text = "static int a = X ? 1 : 2;\nstatic int t[] = {\n    Y ? 3 : 4,\n    A && B,\n};\nint f(void)\n{\n    return 0;\n}\n";
+
+        let (functions, _, pseudo) = parse_with("table.c", text, &options);
+
+        assert_eq!(functions, 2);
+        assert_eq!(pseudo.len(), 1);
+        assert_eq!(pseudo[0].name, FILE_SCOPE);
+        assert_eq!(pseudo[0].cc, 3);
+    }
+
+    /// C++ sources paired with the number of code lines tokei counts in them
+    const CODE_LINE_FIXTURES: [(&str, u64); 9] = [
+        ("/* a */ /* b */\nint x;\n", 1),
+        ("/* a */ int x;\nint y; // trailing\n", 2),
+        ("/* a */ int x; /* b */\n", 0),
+        ("/* start\n   still */ int y;\nint z;\n", 1),
+        ("/* a */ /* b\n   c */\nint x;\n", 1),
+        ("#if 0\nint dead;\n#endif\n\n// done\n", 3),
+        ("char *s = \"/* not a comment\";\nint z;\n", 2),
+        ("const char *q = \"\\\" /* \";\nint w;\n", 2),
+        ("auto s = R\"(a \" /* b)\";\nint z;\n", 2),
+    ];
+
+    #[test]
+    fn code_lines_are_counted_like_tokei()
+    {
+        for (text, expected) in CODE_LINE_FIXTURES
+        {
+            assert_eq!(count_code_lines(text, CommentStyle::C), expected, "{:?}", text);
+        }
+    }
+
+    #[cfg(feature = "tokei")]
+    #[test]
+    fn code_lines_match_tokei()
+    {
+        for (text, _) in CODE_LINE_FIXTURES
+        {
+            let stats = LanguageType::Cpp.parse_from_str(text, &Config::default());
+
+            assert_eq!(count_code_lines(text, CommentStyle::C), stats.code as u64, "{:?}", text);
+        }
+    }
 }
@@ -0,0 +1,188 @@
+use std::fmt;
+use std::str::FromStr;
+
+
+/// A `--fail-above` limit on a file's mean cc: either a plain number, or a
+/// percentile of the files in this run with an optional margin, e.g. `p95+20%`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Threshold
+{
+    Absolute(f64),
+    Percentile
+    {
+        /// 0 to 100
+        rank: f64,
+        /// Percent added to the percentile's value, negative to lower it
+        margin: f64,
+    },
+}
+
+impl FromStr for Threshold
+{
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Threshold, String>
+    {
+        let expected = || format!("expected a number or a percentile like p95 or p95+20% but found '{}'", s);
+
+        let rest = match s.strip_prefix('p')
+        {
+            Some(rest) => rest,
+            None => return s.parse().map(Threshold::Absolute).map_err(|_| expected()),
+        };
+
+        let (rank, margin) = match rest.find(['+', '-'])
+        {
+            Some(i) => {
+                let margin = rest[i..].strip_suffix('%').ok_or_else(expected)?;
+                (&rest[..i], margin.parse::<f64>().map_err(|_| expected())?)
+            },
+            None => (rest, 0.0),
+        };
+
+        let rank: f64 = rank.parse().map_err(|_| expected())?;
+
+        if !(rank > 0.0 && rank <= 100.0)
+        {
+            return Err(format!("percentile must be above 0 and at most 100 but found '{}'", s));
+        }
+
+        Ok(Threshold::Percentile { rank, margin })
+    }
+}
+
+impl fmt::Display for Threshold
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        match self
+        {
+            Threshold::Absolute(value) => write!(f, "{}", value),
+            Threshold::Percentile { rank, margin } if *margin == 0.0 => write!(f, "p{}", rank),
+            Threshold::Percentile { rank, margin } => write!(f, "p{}{:+}%", rank, margin),
+        }
+    }
+}
+
+/// The nearest-rank percentile: the smallest value with at least `rank`
+/// percent of the values at or below it. Always one of the values, so a tiny
+/// project's p95 is its largest value rather than something in between.
+/// Zero for no values
+pub fn percentile(values: &[f64], rank: f64) -> f64
+{
+    if values.is_empty()
+    {
+        return 0.0;
+    }
+
+    let mut sorted = values.to_vec();
+    sorted.sort_by(f64::total_cmp);
+
+    /* multiplied before dividing, so whole ranks of whole counts stay exact.
+     * `rank / 100.0` first rounds, and p7 of 100 values became the 8th */
+    let index = ((rank * sorted.len() as f64 / 100.0).ceil() as usize).clamp(1, sorted.len());
+
+    sorted[index - 1]
+}
+
+impl Threshold
+{
+    /// The limit as a number, given the mean cc of every file gated
+    pub fn resolve(&self, values: &[f64]) -> f64
+    {
+        match self
+        {
+            Threshold::Absolute(value) => *value,
+            Threshold::Percentile { rank, margin } => percentile(values, *rank) * (1.0 + margin / 100.0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    /// 1.0 to n.0, shuffled so sorting is exercised
+    fn values(n: usize) -> Vec<f64>
+    {
+        let mut values: Vec<f64> = (1..=n).map(|v| v as f64).collect();
+        values.reverse();
+        values
+    }
+
+    #[test]
+    fn nearest_rank_of_a_hundred_values()
+    {
+        let values = values(100);
+
+        assert_eq!(percentile(&values, 1.0), 1.0);
+        assert_eq!(percentile(&values, 7.0), 7.0);
+        assert_eq!(percentile(&values, 50.0), 50.0);
+        assert_eq!(percentile(&values, 55.0), 55.0);
+        assert_eq!(percentile(&values, 95.0), 95.0);
+        assert_eq!(percentile(&values, 100.0), 100.0);
+    }
+
+    #[test]
+    fn nearest_rank_matches_integer_math()
+    {
+        for n in 1..=200
+        {
+            let values = values(n);
+
+            for rank in 1..=100
+            {
+                let expected = (rank * n).div_ceil(100);
+
+                assert_eq!(percentile(&values, rank as f64), expected as f64, "p{} of {}", rank, n);
+            }
+        }
+    }
+
+    #[test]
+    fn tiny_projects_use_their_largest_value()
+    {
+        assert_eq!(percentile(&[3.0, 1.0, 2.0], 95.0), 3.0);
+        assert_eq!(percentile(&[3.0, 1.0, 2.0], 50.0), 2.0);
+        assert_eq!(percentile(&[3.0, 1.0, 2.0], 33.0), 1.0);
+        assert_eq!(percentile(&[4.0], 1.0), 4.0);
+        assert_eq!(percentile(&[], 95.0), 0.0);
+    }
+
+    #[test]
+    fn fractional_ranks()
+    {
+        let values = values(1000);
+
+        assert_eq!(percentile(&values, 99.9), 999.0);
+        assert_eq!(percentile(&values, 0.05), 1.0);
+    }
+
+    #[test]
+    fn margin_is_a_percentage_of_the_percentile()
+    {
+        let values = values(100);
+
+        assert_eq!("p95+20%".parse::<Threshold>().unwrap().resolve(&values), 95.0 * 1.2);
+        assert_eq!("p50-10%".parse::<Threshold>().unwrap().resolve(&values), 45.0);
+        assert_eq!("12.5".parse::<Threshold>().unwrap().resolve(&values), 12.5);
+    }
+
+    #[test]
+    fn parse_and_display()
+    {
+        assert_eq!("p95".parse::<Threshold>(), Ok(Threshold::Percentile { rank: 95.0, margin: 0.0 }));
+        assert_eq!("p99+20%".parse::<Threshold>(), Ok(Threshold::Percentile { rank: 99.0, margin: 20.0 }));
+        assert_eq!("10".parse::<Threshold>(), Ok(Threshold::Absolute(10.0)));
+
+        assert!("p0".parse::<Threshold>().is_err());
+        assert!("p101".parse::<Threshold>().is_err());
+        assert!("p95+20".parse::<Threshold>().is_err());
+        assert!("high".parse::<Threshold>().is_err());
+
+        assert_eq!("p95+20%".parse::<Threshold>().unwrap().to_string(), "p95+20%");
+        assert_eq!("p90-5%".parse::<Threshold>().unwrap().to_string(), "p90-5%");
+        assert_eq!("p99".parse::<Threshold>().unwrap().to_string(), "p99");
+    }
+}
@@ -0,0 +1,96 @@
+use std::path::{Path, PathBuf};
+use ignore::Match;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+
+
+/// The top of the git repository `root` is in, if it is in one
+fn repository(root: &Path) -> Option<&Path>
+{
+    root.ancestors().find(|dir| dir.join(".git").exists())
+}
+
+/// Load an ignore file whose patterns are relative to `dir`. A line that
+/// isn't a valid pattern only loses that line, as with git
+fn load(dir: &Path, file: &Path) -> Option<Gitignore>
+{
+    if !file.is_file()
+    {
+        return None;
+    }
+
+    let mut builder = GitignoreBuilder::new(dir);
+    builder.add(file);
+
+    builder.build().ok()
+}
+
+/// The `.gitignore` files that apply to the walk, from the top of the
+/// repository down to the directory being walked, and `.git/info/exclude`.
+/// Files are loaded as their directories are entered, so only the chain of
+/// directories above the current entry is ever held
+pub struct GitIgnores
+{
+    top: PathBuf,
+    /// Directories from `top` down, each with its `.gitignore` if it has one
+    chain: Vec<(PathBuf, Option<Gitignore>)>,
+    /// Loses to every `.gitignore`, as in git
+    exclude: Option<Gitignore>,
+}
+
+impl GitIgnores
+{
+    /// The rules for a walk of `root`. Outside of a repository only the
+    /// `.gitignore` files from `root` down are used
+    pub fn new(root: &Path) -> GitIgnores
+    {
+        let top = repository(root).unwrap_or(root).to_path_buf();
+        let exclude = load(&top, &top.join(".git").join("info").join("exclude"));
+
+        GitIgnores { top, chain: Vec::new(), exclude }
+    }
+
+    /// Move the chain to `dir`, dropping the directories that were left and
+    /// loading the `.gitignore` of each one entered
+    fn descend(&mut self, dir: &Path)
+    {
+        while self.chain.last().is_some_and(|(last, _)| !dir.starts_with(last))
+        {
+            self.chain.pop();
+        }
+
+        let entered: Vec<PathBuf> = dir.ancestors()
+                                       .take_while(|a| a.starts_with(&self.top) && self.chain.last().is_none_or(|(last, _)| a != last))
+                                       .map(Path::to_path_buf)
+                                       .collect();
+
+        for dir in entered.into_iter().rev()
+        {
+            let gitignore = load(&dir, &dir.join(".gitignore"));
+            self.chain.push((dir, gitignore));
+        }
+    }
+
+    /// The pattern that ignores a path, if any. The deepest `.gitignore` with
+    /// a matching pattern decides, so a `!` pattern can bring a path back
+    pub fn matched(&mut self, path: &Path, is_dir: bool) -> Option<String>
+    {
+        self.descend(path.parent()?);
+
+        let matchers = self.chain.iter()
+                                 .rev()
+                                 .filter_map(|(_, gitignore)| gitignore.as_ref())
+                                 .chain(self.exclude.as_ref());
+
+        for gitignore in matchers
+        {
+            match gitignore.matched(path, is_dir)
+            {
+                Match::Ignore(glob) => return Some(glob.original().to_string()),
+                Match::Whitelist(_) => return None,
+                Match::None => {},
+            }
+        }
+
+        None
+    }
+}
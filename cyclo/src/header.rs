@@ -0,0 +1,107 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::escape::json_string;
+
+
+/// What a chart shows, for its title and a footer line, so screenshots of
+/// different runs can be told apart. Everything comes from the run, not from
+/// the machine serving the page
+pub struct Header
+{
+    pub title: String,
+    pub subtitle: String,
+}
+
+impl Header
+{
+    /// The header as the JS the webserver's `plot.js` reads, in a variable
+    /// named after the chart's, e.g. `jsondata_header`
+    pub fn js(&self, chart_id: &str) -> String
+    {
+        format!("var {}_header = {{title: {}, subtitle: {}}};\n", chart_id, json_string(&self.title), json_string(&self.subtitle))
+    }
+}
+
+/// Seconds since the epoch, or `SOURCE_DATE_EPOCH` when it is set so reports
+/// can be reproduced byte for byte
+pub fn now() -> u64
+{
+    if let Some(epoch) = std::env::var("SOURCE_DATE_EPOCH").ok().and_then(|e| e.parse().ok())
+    {
+        return epoch;
+    }
+
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+/// Format seconds since the epoch as `YYYY-MM-DD HH:MM UTC`
+pub fn utc(seconds: u64) -> String
+{
+    let minutes = seconds % 86400 / 60;
+
+    format!("{} {:02}:{:02} UTC", date(seconds), minutes / 60, minutes % 60)
+}
+
+/// Format seconds since the epoch as ISO 8601, `YYYY-MM-DDTHH:MM:SSZ`, as
+/// `--sqlite` records it
+#[cfg(feature = "sqlite")]
+pub fn iso8601(seconds: u64) -> String
+{
+    let time = seconds % 86400;
+
+    format!("{}T{:02}:{:02}:{:02}Z", date(seconds), time / 3600, time % 3600 / 60, time % 60)
+}
+
+/// The `YYYY-MM-DD` date of seconds since the epoch
+fn date(seconds: u64) -> String
+{
+    let days = (seconds / 86400) as i64;
+
+    /* days to a civil date, from Howard Hinnant's date algorithms */
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn header_js_is_named_after_the_chart()
+    {
+        let header = Header { title: "my \"app\"".to_string(), subtitle: "src · 2023-11-14 22:13 UTC · cyclo 0.1.0".to_string() };
+
+        assert_eq!(header.js("jsondata"), "var jsondata_header = {title: \"my \\\"app\\\"\", subtitle: \"src · 2023-11-14 22:13 UTC · cyclo 0.1.0\"};\n");
+        assert!(header.js("matrix").starts_with("var matrix_header = "));
+    }
+
+    #[test]
+    fn utc_dates()
+    {
+        assert_eq!(utc(0), "1970-01-01 00:00 UTC");
+        assert_eq!(utc(1700000000), "2023-11-14 22:13 UTC");
+        assert_eq!(utc(951782400), "2000-02-29 00:00 UTC");
+        assert_eq!(utc(1709251199), "2024-02-29 23:59 UTC");
+        assert_eq!(utc(4102444740), "2099-12-31 23:59 UTC");
+        assert_eq!(utc(253402300799), "9999-12-31 23:59 UTC");
+    }
+
+    #[test]
+    #[cfg(feature = "sqlite")]
+    fn iso8601_dates()
+    {
+        assert_eq!(iso8601(0), "1970-01-01T00:00:00Z");
+        assert_eq!(iso8601(1700000000), "2023-11-14T22:13:20Z");
+        assert_eq!(iso8601(1709251199), "2024-02-29T23:59:59Z");
+    }
+}
@@ -0,0 +1,457 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::{Component, Path, PathBuf};
+
+
+/// Builds the treemap labels, which are paths relative to the analyzed root
+/// prefixed with the root's own name, e.g. `test/sub/main.c`. Labels always use
+/// `/` and never have empty components, however the root was written
+pub struct Labeler
+{
+    /// Each analyzed root with the label of its box. Roots never contain one
+    /// another, so a path is under at most one
+    roots: Vec<(PathBuf, String)>,
+    /// The top label when it stands for no directory, with several roots
+    synthetic: Option<String>,
+}
+
+/// Name of the top node with several roots and no `--root-label`
+const SYNTHETIC_ROOT: &str = "project";
+
+/// A root label from `--root-label`. A `/` would be taken as a level of its own
+fn given_label(root_label: Option<&str>) -> Option<String>
+{
+    root_label.filter(|label| !label.is_empty()).map(|label| label.replace('/', "_"))
+}
+
+/// The name of the directory a root resolves to. `.` and `./src/` have no
+/// useful name of their own
+fn directory_name(root: &Path) -> Option<String>
+{
+    let resolved = root.canonicalize().unwrap_or_else(|_| root.to_path_buf());
+
+    resolved.file_name().map(|name| name.to_string_lossy().into_owned())
+}
+
+impl Labeler
+{
+    /// `root_label` renames the top node. Without it the root's directory
+    /// name is used
+    pub fn new(root: &Path, root_label: Option<&str>) -> Labeler
+    {
+        let root_label = given_label(root_label).or_else(|| directory_name(root))
+                                                .unwrap_or_else(|| "root".to_string());
+
+        Labeler
+        {
+            roots: vec![(root.to_path_buf(), root_label)],
+            synthetic: None,
+        }
+    }
+
+    /// Labels for several roots charted together. Each root is a box of its
+    /// own directly under a top node that stands for no directory, named
+    /// `root_label` or `project`, so roots at different depths on disk still
+    /// sit side by side. Roots are named after their directory, with ` (2)`,
+    /// ` (3)`, ... added when two have the same name
+    pub fn synthetic(roots: &[PathBuf], root_label: Option<&str>) -> Labeler
+    {
+        let top = given_label(root_label).unwrap_or_else(|| SYNTHETIC_ROOT.to_string());
+        let mut named: Vec<(PathBuf, String)> = Vec::new();
+
+        for root in roots
+        {
+            let name = directory_name(root).unwrap_or_else(|| "root".to_string());
+            let mut label = format!("{}/{}", top, name);
+            let mut n = 1;
+
+            while named.iter().any(|(_, other)| *other == label)
+            {
+                n += 1;
+                label = format!("{}/{} ({})", top, name, n);
+            }
+
+            named.push((root.clone(), label));
+        }
+
+        Labeler
+        {
+            roots: named,
+            synthetic: Some(top),
+        }
+    }
+
+    /// Whether `label` is the top node of several roots, which has no
+    /// directory on disk
+    pub fn is_synthetic(&self, label: &str) -> bool
+    {
+        self.synthetic.as_deref() == Some(label)
+    }
+
+    /// Label for a path found while walking the root. Returns None if the path
+    /// isn't under the root, which can happen when following symlinks
+    pub fn label(&self, path: &Path) -> Option<String>
+    {
+        let (relative, mut label) = self.roots.iter()
+                                              .find_map(|(root, label)| Some((path.strip_prefix(root).ok()?, label.clone())))?;
+
+        for component in relative.components()
+        {
+            match component
+            {
+                Component::Normal(name) => {
+                    label.push('/');
+                    label.push_str(&name.to_string_lossy());
+                },
+                Component::CurDir => continue,
+                _ => return None,
+            }
+        }
+
+        Some(label)
+    }
+}
+
+/// The label of the directory containing `label`, or an empty string for the
+/// root, which is what plotly expects as the parent of the top node
+pub fn parent_label(label: &str) -> &str
+{
+    match label.rsplit_once('/')
+    {
+        Some((parent, _)) => parent,
+        None => "",
+    }
+}
+
+/// Finds labels that differ only in case, such as `src/Utils.c` and
+/// `src/utils.c`. Those collide when checked out on a case-insensitive
+/// filesystem, and case-insensitive consumers treat them as one node.
+/// Comparison is on the label strings only so every platform agrees
+#[derive(Default)]
+pub struct CaseFolder
+{
+    /// lowercased label -> the folded and original spellings seen first
+    spellings: HashMap<String, (String, String)>,
+    /// lowercased label -> every spelling seen, for labels with more than one
+    collisions: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl CaseFolder
+{
+    /// Record a label and return it with each level spelled the way it was
+    /// first seen, so `test/Src/a.c` becomes `test/src/a.c` if `test/src` came
+    /// first
+    pub fn fold(&mut self, label: &str) -> String
+    {
+        let mut folded = String::new();
+        let mut raw = String::new();
+
+        for component in label.split('/')
+        {
+            if !raw.is_empty()
+            {
+                raw.push('/');
+            }
+            raw.push_str(component);
+
+            let spelled = if folded.is_empty() { component.to_string() } else { format!("{}/{}", folded, component) };
+            let key = raw.to_lowercase();
+
+            let (first, first_raw) = self.spellings.entry(key.clone()).or_insert_with(|| (spelled, raw.clone()));
+
+            /* only report the level where the spelling differs, not every
+             * label underneath it */
+            if first.rsplit('/').next() != Some(component)
+            {
+                let spellings = self.collisions.entry(key).or_default();
+                spellings.insert(first_raw.clone());
+                spellings.insert(raw.clone());
+            }
+
+            folded = first.clone();
+        }
+
+        folded
+    }
+
+    /// Each group of labels that differ only in case
+    pub fn collisions(&self) -> impl Iterator<Item = &BTreeSet<String>>
+    {
+        self.collisions.values()
+    }
+}
+
+/// A `--path-rewrite from=<prefix>,to=<prefix>` rule, applied to labels so they
+/// match the paths other tools use, e.g. when sources are copied into a
+/// staging directory before analysis. Files are still opened where they are
+#[derive(Debug, Clone)]
+pub struct PathRewrite
+{
+    pub from: String,
+    pub to: String,
+}
+
+impl std::str::FromStr for PathRewrite
+{
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<PathRewrite, String>
+    {
+        let mut from = None;
+        let mut to = None;
+
+        for part in s.split(',')
+        {
+            match part.split_once('=')
+            {
+                Some(("from", value)) => from = Some(value.trim_matches('/').to_string()),
+                Some(("to", value)) => to = Some(value.trim_matches('/').to_string()),
+                _ => return Err(format!("expected from=<prefix>,to=<prefix> but found '{}'", part)),
+            }
+        }
+
+        match (from, to)
+        {
+            (Some(from), Some(to)) if !from.is_empty() && !to.is_empty() => Ok(PathRewrite { from, to }),
+            _ => Err("expected from=<prefix>,to=<prefix> with neither prefix empty".to_string()),
+        }
+    }
+}
+
+impl PathRewrite
+{
+    /// The label with `from` replaced, if it starts with `from` as whole
+    /// components
+    fn apply(&self, label: &str) -> Option<String>
+    {
+        let rest = label.strip_prefix(&self.from)?;
+
+        if !rest.is_empty() && !rest.starts_with('/')
+        {
+            return None;
+        }
+
+        Some(format!("{}{}", self.to, rest))
+    }
+}
+
+/// What happened to a label passed through the rewrite rules
+pub enum RewriteResult
+{
+    /// No rule matched
+    Unmatched,
+    /// Rules matched but disagreed on the result, so it was left alone
+    Conflict,
+    Rewritten(String),
+}
+
+/// Apply every rule to a label. Rules that give the same result aren't a
+/// conflict
+pub fn rewrite(rewrites: &[PathRewrite], label: &str) -> RewriteResult
+{
+    let mut result: Option<String> = None;
+
+    for rewritten in rewrites.iter().filter_map(|r| r.apply(label))
+    {
+        match &result
+        {
+            Some(previous) if *previous != rewritten => return RewriteResult::Conflict,
+            _ => result = Some(rewritten),
+        }
+    }
+
+    match result
+    {
+        Some(label) => RewriteResult::Rewritten(label),
+        None => RewriteResult::Unmatched,
+    }
+}
+
+/// The steps a file's label went through, so a collision can be traced back to
+/// the rule that caused it, e.g. `path /src/a.c -> label src/a.c ->
+/// --path-rewrite lib/a.c`
+#[derive(Debug, Clone, Default)]
+pub struct Provenance
+{
+    steps: Vec<(&'static str, String)>,
+}
+
+impl Provenance
+{
+    pub fn push(&mut self, step: &'static str, value: &str)
+    {
+        self.steps.push((step, value.to_string()));
+    }
+
+    /// The steps joined up, each value passed through `show` first so the
+    /// chain can be anonymized
+    pub fn shown(&self, mut show: impl FnMut(&str, &str) -> String) -> String
+    {
+        let steps: Vec<String> = self.steps.iter()
+                                           .map(|(step, value)| format!("{} {}", step, show(step, value)))
+                                           .collect();
+
+        steps.join(" -> ")
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn several_roots_are_top_level_boxes()
+    {
+        let roots = [PathBuf::from("/nonexistent/a/firmware"), PathBuf::from("/nonexistent/b/c/host")];
+        let labeler = Labeler::synthetic(&roots, None);
+
+        assert_eq!(labeler.label(Path::new("/nonexistent/a/firmware/main.c")).as_deref(), Some("project/firmware/main.c"));
+        assert_eq!(labeler.label(Path::new("/nonexistent/b/c/host/net/tcp.c")).as_deref(), Some("project/host/net/tcp.c"));
+        assert_eq!(labeler.label(Path::new("/nonexistent/b/c/host")).as_deref(), Some("project/host"));
+        assert_eq!(labeler.label(Path::new("/nonexistent/a/other.c")), None);
+
+        assert!(labeler.is_synthetic("project"));
+        assert!(!labeler.is_synthetic("project/host"));
+    }
+
+    #[test]
+    fn roots_with_the_same_name_are_numbered()
+    {
+        let roots = [PathBuf::from("/nonexistent/a/src"), PathBuf::from("/nonexistent/b/src"), PathBuf::from("/nonexistent/c/src")];
+        let labeler = Labeler::synthetic(&roots, Some("all"));
+
+        assert_eq!(labeler.label(Path::new("/nonexistent/a/src/x.c")).as_deref(), Some("all/src/x.c"));
+        assert_eq!(labeler.label(Path::new("/nonexistent/b/src/x.c")).as_deref(), Some("all/src (2)/x.c"));
+        assert_eq!(labeler.label(Path::new("/nonexistent/c/src/x.c")).as_deref(), Some("all/src (3)/x.c"));
+    }
+
+    #[test]
+    fn single_root_is_the_top_box()
+    {
+        let labeler = Labeler::new(Path::new("/nonexistent/test"), None);
+
+        assert_eq!(labeler.label(Path::new("/nonexistent/test/sub/main.c")).as_deref(), Some("test/sub/main.c"));
+        assert_eq!(labeler.label(Path::new("/nonexistent/test")).as_deref(), Some("test"));
+        assert!(!labeler.is_synthetic("test"));
+
+        let renamed = Labeler::new(Path::new("/nonexistent/test"), Some("a/b"));
+
+        assert_eq!(renamed.label(Path::new("/nonexistent/test/main.c")).as_deref(), Some("a_b/main.c"));
+    }
+
+    #[test]
+    fn parents()
+    {
+        assert_eq!(parent_label("test/sub/main.c"), "test/sub");
+        assert_eq!(parent_label("test"), "");
+    }
+
+    #[test]
+    fn roots_written_any_way_give_clean_labels()
+    {
+        let cwd = std::env::current_dir().unwrap();
+        let name = cwd.file_name().unwrap().to_string_lossy().into_owned();
+
+        let dot = Labeler::new(Path::new("."), None);
+        assert_eq!(dot.label(Path::new("./src/main.rs")), Some(format!("{}/src/main.rs", name)));
+        assert_eq!(dot.label(Path::new(".")), Some(name.clone()));
+
+        let src = Labeler::new(Path::new("./src/"), None);
+        assert_eq!(src.label(Path::new("./src/labels.rs")).as_deref(), Some("src/labels.rs"));
+
+        let absolute = Labeler::new(&cwd.join("src"), None);
+        assert_eq!(absolute.label(&cwd.join("src").join("labels.rs")).as_deref(), Some("src/labels.rs"));
+    }
+
+    #[test]
+    fn repeated_separators_leave_no_empty_components()
+    {
+        let labeler = Labeler::new(Path::new("/nonexistent//test/"), None);
+
+        assert_eq!(labeler.label(Path::new("/nonexistent/test//sub///main.c")).as_deref(), Some("test/sub/main.c"));
+        assert_eq!(labeler.label(Path::new("/nonexistent/test/./main.c")).as_deref(), Some("test/main.c"));
+    }
+
+    #[test]
+    fn paths_that_climb_out_have_no_label()
+    {
+        let labeler = Labeler::new(Path::new("/nonexistent/test"), None);
+
+        assert_eq!(labeler.label(Path::new("/nonexistent/test/../other/main.c")), None);
+        assert_eq!(labeler.label(Path::new("/nonexistent/tests/main.c")), None);
+    }
+
+    #[test]
+    fn deep_paths_are_labelled_in_full()
+    {
+        let labeler = Labeler::new(Path::new("/nonexistent/test"), None);
+        let deep: PathBuf = ["/nonexistent/test"].into_iter().chain(["d"; 200]).chain(["main.c"]).collect();
+
+        let label = labeler.label(&deep).unwrap();
+
+        assert_eq!(label.split('/').count(), 202);
+        assert!(label.ends_with("/d/main.c"));
+    }
+
+    #[test]
+    fn rewrites_match_whole_components()
+    {
+        let rules: Vec<PathRewrite> = vec!["from=stage/src,to=src".parse().unwrap()];
+
+        assert!(matches!(rewrite(&rules, "stage/src/a.c"), RewriteResult::Rewritten(label) if label == "src/a.c"));
+        assert!(matches!(rewrite(&rules, "stage/src"), RewriteResult::Rewritten(label) if label == "src"));
+        assert!(matches!(rewrite(&rules, "stage/srcs/a.c"), RewriteResult::Unmatched));
+        assert!(matches!(rewrite(&rules, "other/a.c"), RewriteResult::Unmatched));
+    }
+
+    #[test]
+    fn rules_that_disagree_conflict()
+    {
+        let agree: Vec<PathRewrite> = vec!["from=a,to=x".parse().unwrap(), "from=a/,to=/x/".parse().unwrap()];
+        let disagree: Vec<PathRewrite> = vec!["from=a,to=x".parse().unwrap(), "from=a/b,to=y".parse().unwrap()];
+
+        assert!(matches!(rewrite(&agree, "a/b/c.c"), RewriteResult::Rewritten(label) if label == "x/b/c.c"));
+        assert!(matches!(rewrite(&disagree, "a/b/c.c"), RewriteResult::Conflict));
+        assert!(matches!(rewrite(&disagree, "a/c.c"), RewriteResult::Rewritten(label) if label == "x/c.c"));
+    }
+
+    #[test]
+    fn bad_rewrite_rules_are_refused()
+    {
+        assert!("from=a".parse::<PathRewrite>().is_err());
+        assert!("from=/,to=b".parse::<PathRewrite>().is_err());
+        assert!("from=a,into=b".parse::<PathRewrite>().is_err());
+    }
+
+    #[test]
+    fn provenance_is_shown_in_order()
+    {
+        let mut provenance = Provenance::default();
+        provenance.push("path", "/work/stage/a.c");
+        provenance.push("label", "stage/a.c");
+        provenance.push("--path-rewrite", "src/a.c");
+
+        assert_eq!(provenance.shown(|_, value| value.to_string()), "path /work/stage/a.c -> label stage/a.c -> --path-rewrite src/a.c");
+
+        /* values can be hidden step by step, e.g. when anonymizing */
+        let hidden = provenance.shown(|step, value| if step == "path" { "<path>".to_string() } else { value.to_uppercase() });
+
+        assert_eq!(hidden, "path <path> -> label STAGE/A.C -> --path-rewrite SRC/A.C");
+        assert_eq!(Provenance::default().shown(|_, value| value.to_string()), "");
+    }
+
+    #[test]
+    fn case_collisions_are_reported_once_per_level()
+    {
+        let mut folder = CaseFolder::default();
+
+        assert_eq!(folder.fold("test/src/a.c"), "test/src/a.c");
+        assert_eq!(folder.fold("test/Src/b.c"), "test/src/b.c");
+        assert_eq!(folder.fold("test/SRC/c.c"), "test/src/c.c");
+        assert_eq!(folder.fold("test/other/A.c"), "test/other/A.c");
+
+        let collisions: Vec<Vec<&str>> = folder.collisions().map(|c| c.iter().map(String::as_str).collect()).collect();
+
+        assert_eq!(collisions, [["test/SRC", "test/Src", "test/src"]]);
+    }
+}
@@ -0,0 +1,99 @@
+use std::path::{Component, Path};
+use std::process::Command;
+
+use crate::escape;
+
+
+/// Builds links from each file back to a code browser, from a template such
+/// as `https://git.example.com/repo/blob/{rev}/{path}#L{line}`
+pub struct LinkTemplate
+{
+    template: String,
+    rev: Option<String>,
+    /// Where the analyzed root sits inside its repository, e.g. `src/net/`,
+    /// so links are relative to the repository rather than the root
+    prefix: String,
+}
+
+impl LinkTemplate
+{
+    /// `rev` is used for `{rev}` if given, otherwise the commit checked out in
+    /// `root` is asked for from git
+    pub fn new(template: &str, rev: Option<&str>, root: &Path) -> LinkTemplate
+    {
+        let rev = match rev
+        {
+            Some(rev) => Some(rev.to_string()),
+            None => git(root, &["rev-parse", "HEAD"]).filter(|rev| !rev.is_empty()),
+        };
+
+        LinkTemplate
+        {
+            template: template.to_string(),
+            rev,
+            prefix: git(root, &["rev-parse", "--show-prefix"]).unwrap_or_default(),
+        }
+    }
+
+    /// Link to `line` of the file at `relative`, a path under the analyzed
+    /// root. Returns None when the template needs a revision and there isn't
+    /// one, since a link to the wrong revision is worse than no link
+    pub fn url(&self, relative: &Path, line: u64) -> Option<String>
+    {
+        let mut segments: Vec<String> = self.prefix.split('/')
+                                                   .filter(|s| !s.is_empty())
+                                                   .map(String::from)
+                                                   .collect();
+
+        for component in relative.components()
+        {
+            match component
+            {
+                Component::Normal(name) => segments.push(name.to_string_lossy().into_owned()),
+                Component::CurDir => continue,
+                _ => return None,
+            }
+        }
+
+        self.url_for(&segments.join("/"), line)
+    }
+
+    /// Link to `line` of the file at `path`, which is already relative to the
+    /// repository, e.g. a label from `--path-rewrite`
+    pub fn url_for(&self, path: &str, line: u64) -> Option<String>
+    {
+        if self.template.contains("{rev}") && self.rev.is_none()
+        {
+            return None;
+        }
+
+        let segments: Vec<String> = path.split('/')
+                                        .filter(|s| !s.is_empty())
+                                        .map(escape::url_segment)
+                                        .collect();
+
+        Some(self.template.replace("{rev}", &escape::url_segment(self.rev.as_deref().unwrap_or_default()))
+                          .replace("{path}", &segments.join("/"))
+                          .replace("{line}", &line.to_string()))
+    }
+}
+
+/// Run a git query in the repository containing `root`, returning its trimmed
+/// output. None if git isn't installed or `root` isn't in a repository
+pub fn git(root: &Path, args: &[&str]) -> Option<String>
+{
+    let dir = if root.is_dir() { root } else { root.parent()? };
+
+    let output = Command::new("git").arg("-C")
+                                    .arg(dir)
+                                    .args(args)
+                                    .output()
+                                    .ok()?;
+
+    if !output.status.success()
+    {
+        return None;
+    }
+
+    Some(String::from_utf8(output.stdout).ok()?.trim().to_string())
+}
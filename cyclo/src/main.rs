@@ -1,139 +1,1575 @@
-use std::{fs,assert_eq,assert};
-use std::io::Write;
-use std::path::PathBuf;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use std::vec::Vec;
-use clap::Parser;
-use walkdir::WalkDir;
+use clap::{CommandFactory, FromArgMatches, Parser, ValueEnum, ValueSource};
+use regex::Regex;
 
+mod anonymize;
+mod artifact;
+mod auto_exclude;
+mod category;
+mod cccc;
+mod codeclimate;
+mod checkpoint;
+mod color_scale;
+mod config;
+mod csv;
+mod diagnostics;
+mod dot;
+mod escape;
 mod file_parser;
+mod gate;
+mod gitignore;
+mod header;
+mod labels;
+mod links;
+mod markdown;
+mod matrix;
+mod planner;
+mod readme;
+mod report;
+mod root;
+mod sarif;
+mod source;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+mod vendored;
+mod webhook;
 
-use file_parser::FileParser;
+use anonymize::{Anonymize, Anonymizer};
+use artifact::{ArtifactOptions, CompressMode};
+use category::{Category, CategoryMode, Classified, Classifier};
+use checkpoint::Checkpoint;
+use color_scale::ColorOptions;
+use diagnostics::{Code, Diagnostic, ErrorsFormat, Reporter, Verbosity};
+use gate::Threshold;
+use header::Header;
+use file_parser::{FileParser, FileParserError, ParseOptions};
+use labels::{CaseFolder, Labeler, PathRewrite, Provenance, RewriteResult};
+use links::LinkTemplate;
+use planner::{PathGlob, PlanOptions, Planned, Planner, SkipInventory, SkipReason, UnsupportedTally};
+use report::{Format, Record, Rollup};
+use webhook::{FileSummary, WebhookOptions};
 
 
+/// Exit status when `--fail-above` or `--fail-over-mean` is exceeded, so CI can
+/// tell a gate from an error, which exits with 1 (2 for bad arguments)
+const EXIT_THRESHOLD: i32 = 3;
+
+/// Where `js` reports go without `-o` when run from the `cyclo` directory of
+/// a checkout, so the webserver picks them up
+const WEBSERVER_OUTPUT: &str = "../webserver/web/scripts/cyclo.js";
+
+/// Which metric the treemap colors are taken from
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum ColorBy
+{
+    /// Mean function cyclomatic complexity
+    Cc,
+    /// Mean function compile-time complexity, see `--count-compile-time`
+    CompileTime,
+}
+
+/// What the webserver chart shows
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Chart
+{
+    /// Every file and directory, sized by nloc
+    Treemap,
+    /// A heatmap of directories against metrics, see `--rollup-depth`
+    Matrix,
+}
+
 #[derive(Parser,Debug)]
 #[clap(name="cyclo", about="visualize complexity")]
 struct Args
 {
-    /// Relative path to directory to analyze
-    #[clap(short = 'p', long, value_parser)]
-    path: PathBuf,
+    /// Path to the directory or file to analyze. A leading `~` is expanded.
+    /// Repeatable, to chart several directories as one tree with each of
+    /// them a top-level box
+    #[clap(short = 'p', long, value_parser, required_unless_present = "files-from")]
+    path: Vec<PathBuf>,
+    /// Analyze the files listed in this file, one per line, instead of
+    /// walking `--path`. `-` reads the list from stdin, e.g. from
+    /// `git diff --name-only`. Listed files that don't exist are skipped with
+    /// a warning
+    #[clap(long, value_parser, value_name = "FILE", conflicts_with = "path")]
+    files_from: Option<PathBuf>,
+    /// Settings file to use instead of the nearest `cyclo.toml` above the
+    /// analyzed path. Flags given here win over it
+    #[clap(long, value_parser)]
+    config: Option<PathBuf>,
+    /// Print the settings after merging flags, the config file, and
+    /// defaults, with where each came from, then exit
+    #[clap(long, action)]
+    print_config: bool,
+    /// What the report is written as
+    #[clap(short = 'f', long, value_enum, default_value = "js")]
+    format: Format,
+    /// Where to write the report. Missing directories are created. Defaults
+    /// to the webserver's `../webserver/web/scripts/cyclo.js` for `js` if that
+    /// directory exists, `cyclo.js` if not, and stdout otherwise
+    #[clap(short = 'o', long, value_parser)]
+    output: Option<PathBuf>,
+    /// Mean cc above which a file is a finding in `sarif` and `codeclimate`
+    /// reports
+    #[clap(long, value_parser, default_value_t = 10.0)]
+    threshold: f64,
+    /// Report at most this many findings, the worst ones. `sarif` reports
+    /// follow them with a note saying how many more there were
+    #[clap(long, value_parser)]
+    max_findings: Option<usize>,
+    /// Levels below the root drawn in `dot` reports, so a large tree still
+    /// gives a readable graph
+    #[clap(long, value_parser, default_value_t = 4)]
+    dot_depth: usize,
+    /// Exit with status 3 if any file's mean cc is above this: a number, or a
+    /// percentile of this run's files with an optional margin, e.g. `p95` or
+    /// `p95+20%`. Generated and vendored files are left out
+    #[clap(long, alias = "fail-over", value_parser)]
+    fail_above: Option<Threshold>,
+    /// Exit with status 3 if the mean cc over all functions of every file is
+    /// above this. Generated and vendored files are left out
+    #[clap(long, value_parser)]
+    fail_over_mean: Option<f64>,
+    /// Number of files listed in `markdown` reports
+    #[clap(long, value_parser, default_value_t = 10)]
+    top: usize,
+    /// Append the run to this SQLite database, to track complexity over time
+    #[cfg(feature = "sqlite")]
+    #[clap(long, value_parser)]
+    sqlite: Option<PathBuf>,
     /// Whether to write a debug file
     #[clap(short = 'd', long, action)]
     debug: bool,
+    /// Where to write the debug file, instead of `debug.txt` next to the
+    /// `-o` report or in the current directory. Implies `--debug`
+    #[clap(long, value_parser)]
+    debug_path: Option<PathBuf>,
+    /// Comma-separated file and directory names to skip, replacing the default
+    /// set (.git, .hg, .svn, node_modules, target, build)
+    #[clap(long, value_parser, use_value_delimiter = true, conflicts_with = "no-default-ignores")]
+    default_ignores: Option<Vec<String>>,
+    /// Comma-separated names to skip as well as the default set, e.g.
+    /// `_build,.cache`. Repeatable
+    #[clap(long, value_parser, use_value_delimiter = true)]
+    extra_ignores: Vec<String>,
+    /// Don't skip any names by default. `--extra-ignores` still applies
+    #[clap(long, action)]
+    no_default_ignores: bool,
+    /// Comma-separated extensions to analyze, with or without the dot, e.g.
+    /// `c,cpp`. Other files are skipped as not included
+    #[clap(long, value_parser, use_value_delimiter = true)]
+    extensions: Option<Vec<String>>,
+    /// Glob of paths to skip, relative to the root, e.g. `third_party/**` or
+    /// `**/*_generated.c`. Matching directories aren't descended into.
+    /// Repeatable
+    #[clap(long, value_parser)]
+    exclude: Vec<String>,
+    /// Glob of files to analyze, relative to the root, leaving out the rest
+    /// while keeping the labels of a run over the whole root. `--exclude`
+    /// wins over it. Repeatable
+    #[clap(long, value_parser)]
+    include: Vec<String>,
+    /// Don't skip the directories of the ecosystems detected at the root,
+    /// e.g. `node_modules` when there is a `package.json`
+    #[clap(long, action)]
+    no_auto_exclude: bool,
+    /// Don't skip what `.gitignore` files and `.git/info/exclude` ignore
+    #[clap(long, action)]
+    no_ignore: bool,
+    /// Count ternaries in file-scope initializers, and add a synthetic
+    /// `<file-scope>` function for their branching (C/C++ only)
+    #[clap(long, action)]
+    count_global_init: bool,
+    /// Count `if constexpr`, `if consteval`, and conditional `noexcept` as
+    /// compile-time complexity, kept separate from cc (C++ only)
+    #[clap(long, action)]
+    count_compile_time: bool,
+    /// Count function-like macro definitions as pseudo-functions of the file
+    /// that defines them (C/C++ only)
+    #[clap(long, action)]
+    macros_as_functions: bool,
+    /// Leave out files that aren't valid UTF-8 with a warning, instead of
+    /// replacing the invalid bytes and flagging the file in `json` reports
+    #[clap(long, action)]
+    strict_utf8: bool,
+    /// Chart written in `js` reports
+    #[clap(long, value_enum, default_value = "treemap")]
+    chart: Chart,
+    /// How many directories below the root make up a row of the `matrix`
+    /// chart
+    #[clap(long, value_parser, default_value_t = 1)]
+    rollup_depth: usize,
+    /// Metric used to color the treemap
+    #[clap(long, value_enum, default_value = "cc")]
+    color_by: ColorBy,
+    /// Name of the JS variable holding the chart data, so several charts can
+    /// share a page. Without a value it is derived from the analyzed
+    /// directory name. Defaults to `jsondata`
+    #[clap(long, value_parser)]
+    chart_id: Option<Option<String>>,
+    /// Format of errors, warnings, and skips
+    #[clap(long, value_enum, default_value = "human")]
+    errors_format: ErrorsFormat,
+    /// Write diagnostics to this file instead of stderr
+    #[clap(long, value_parser)]
+    errors_file: Option<PathBuf>,
+    /// Show the first paragraph of each directory's README in the treemap
+    /// hover text
+    #[clap(long, action, conflicts_with = "anonymize")]
+    with_readmes: bool,
+    /// Longest label shown in the treemap boxes. Longer paths are shortened in
+    /// the middle; the full path stays in the hover text
+    #[clap(long, value_parser, default_value_t = 60)]
+    max_label_len: usize,
+    /// Also write a gzip compressed copy of the output (`alongside`, the
+    /// default), or only the compressed copy (`instead`)
+    #[clap(long, value_enum)]
+    compress_output: Option<Option<CompressMode>>,
+    /// Name shown for the top node of the chart instead of the analyzed
+    /// directory's name
+    #[clap(long, value_parser)]
+    root_label: Option<String>,
+    /// Title of `js` charts. Defaults to the top node's name
+    #[clap(long, value_parser)]
+    title: Option<String>,
+    /// Line under `js` charts. Defaults to the analyzed directory's name, the
+    /// git branch and commit, when the report was made, and the cyclo version
+    #[clap(long, value_parser)]
+    subtitle: Option<String>,
+    /// Leave the time out of every output: the default `--subtitle`, and the
+    /// `timestamp` of `--sqlite` runs, which is left empty. Two runs over the
+    /// same tree then write identical files
+    #[clap(long, action)]
+    no_timestamps: bool,
+    /// Rewrite the start of labels, e.g. `from=staging,to=src`, so they match
+    /// repository paths when sources were analyzed somewhere else. Applies to
+    /// the chart and links, not to where files are read from. Repeatable
+    #[clap(long, value_parser)]
+    path_rewrite: Vec<PathRewrite>,
+    /// Link each file to a code browser from its hover text. `{path}`,
+    /// `{rev}`, and `{line}` are substituted, e.g.
+    /// `https://git.example.com/repo/blob/{rev}/{path}#L{line}`
+    #[clap(long, value_parser)]
+    link_template: Option<String>,
+    /// Revision used for `{rev}` in `--link-template`. Defaults to the
+    /// commit checked out in the analyzed directory
+    #[clap(long, value_parser, requires = "link-template")]
+    link_rev: Option<String>,
+    /// Write a .sha256 sidecar for each output file
+    #[clap(long, action)]
+    checksum: bool,
+    /// Merge files whose labels differ only in case, e.g. `Utils.c` and
+    /// `utils.c`, into one box. They are reported either way
+    #[clap(long, action)]
+    merge_case_collisions: bool,
+    /// POST a JSON summary of the run here once the output is written
+    #[clap(long, value_parser, env = "CYCLO_WEBHOOK_URL", hide_env_values = true)]
+    webhook_url: Option<String>,
+    /// Seconds to wait for the webhook to answer
+    #[clap(long, value_parser, default_value_t = 10)]
+    webhook_timeout: u64,
+    /// Key used to sign the webhook payload, sent as an HMAC-SHA256 in the
+    /// `X-Cyclo-Signature` header
+    #[clap(long, value_parser, env = "CYCLO_WEBHOOK_SECRET", hide_env_values = true)]
+    webhook_secret: Option<String>,
+    /// Fail the run if the webhook can't be reached. Otherwise it is only a
+    /// warning
+    #[clap(long, action)]
+    webhook_required: bool,
+    /// Replace file and directory names with stable hashed tokens in every
+    /// output, keeping the structure and extensions
+    #[clap(long, value_enum)]
+    anonymize: Option<Anonymize>,
+    /// Write the mapping from tokens back to the original names here. Keep it
+    /// with the team that owns the code
+    #[clap(long, value_parser, requires = "anonymize")]
+    anonymize_map: Option<PathBuf>,
+    /// Look for license headers naming another project and treat those files
+    /// as vendored: marked in the hover text (`include`, the default), left
+    /// out (`exclude`), or moved under a `vendored` branch (`separate`)
+    #[clap(long, value_enum)]
+    detect_vendored: Option<Option<CategoryMode>>,
+    /// Text identifying copyright lines of our own, e.g. `Example Corp`.
+    /// Compared case-insensitively. Repeatable
+    #[clap(long, value_parser, requires = "detect-vendored")]
+    our_copyright: Vec<String>,
+    /// Treat build output that gets compiled, like Qt's `moc_*.cpp`, as
+    /// generated: moved under a `generated` branch (`separate`, the default),
+    /// marked in the hover text (`include`), or left out (`exclude`)
+    #[clap(long, value_enum)]
+    detect_generated: Option<Option<CategoryMode>>,
+    /// Regex matched against file names to find generated files, replacing
+    /// the moc and qrc defaults. Repeatable
+    #[clap(long, value_parser, requires = "detect-generated")]
+    generated_pattern: Vec<Regex>,
+    /// Append the results of each file here as soon as it is parsed, so an
+    /// interrupted run can be finished with `--resume`
+    #[clap(long, value_parser)]
+    checkpoint: Option<PathBuf>,
+    /// Reuse the results in `--checkpoint` for files that haven't changed
+    /// since, and parse only the rest
+    #[clap(long, action, requires = "checkpoint")]
+    resume: bool,
+    /// List the files that would be analyzed, and what was skipped, then exit.
+    /// With `-f json` each is a JSON object on a line of its own
+    #[clap(long, action)]
+    dry_run: bool,
+    /// Show each file as it is analyzed. Twice also shows each skipped entry
+    /// and why
+    #[clap(short = 'v', long, action = clap::ArgAction::Count, conflicts_with = "quiet")]
+    verbose: u8,
+    /// Show errors only, no warnings or summary
+    #[clap(short = 'q', long, action)]
+    quiet: bool,
+}
+
+/// The path to show for an entry, anonymized with `--anonymize paths`
+fn shown_path(anonymizer: &mut Option<Anonymizer>, labeler: &Labeler, path: &Path) -> PathBuf
+{
+    match anonymizer
+    {
+        Some(anonymizer) => anonymizer.path(labeler, path),
+        None => path.to_path_buf(),
+    }
+}
+
+/// Read the paths for `--files-from`, one per line, from the file or from
+/// stdin for `-`. Blank lines and Windows line endings are ignored
+fn files_from(source: &Path) -> io::Result<Vec<PathBuf>>
+{
+    let list = if source == Path::new("-")
+    {
+        io::read_to_string(io::stdin())?
+    }
+    else
+    {
+        std::fs::read_to_string(source)?
+    };
+
+    Ok(list.lines()
+           .map(|line| line.trim_end_matches('\r'))
+           .filter(|line| !line.trim().is_empty())
+           .map(PathBuf::from)
+           .collect())
+}
+
+/// Print every planned file with its language and size, followed by the skip
+/// inventory. With `json` each file is a JSON object on a line of its own
+/// instead, and there is no inventory. Nothing is parsed
+fn dry_run(planner: Planner, labeler: &Labeler, anonymizer: &mut Option<Anonymizer>, json: bool)
+{
+    let mut skips = SkipInventory::default();
+    let mut unsupported = UnsupportedTally::default();
+    let mut count: u64 = 0;
+
+    for planned in planner
+    {
+        match planned
+        {
+            Planned::Analyze(candidate) => {
+                count += 1;
+
+                let path = root::display(&shown_path(anonymizer, labeler, candidate.entry.path()));
+
+                if json
+                {
+                    println!(r#"{{"action":"analyze","path":{},"language":{},"size":{}}}"#, escape::json_string(&path),
+                                                                                          escape::json_string(candidate.language),
+                                                                                          candidate.size);
+                }
+                else
+                {
+                    println!("analyze\t{}\t{}\t{}", escape::strip_control(&path), candidate.language, candidate.size);
+                }
+            },
+            Planned::Skip(skip) => {
+                let path = root::display(&shown_path(anonymizer, labeler, &skip.path));
+
+                if json
+                {
+                    println!(r#"{{"action":"skip","path":{},"reason":{},"size":{}}}"#, escape::json_string(&path),
+                                                                                     escape::json_string(&skip.reason.to_string()),
+                                                                                     skip.size);
+                }
+                else
+                {
+                    println!("skip\t{}\t{}", escape::strip_control(&path), escape::strip_control(&skip.reason.to_string()));
+                }
+
+                skips.record(&skip);
+                unsupported.record(&skip);
+            }
+        }
+    }
+
+    if json
+    {
+        return;
+    }
+
+    println!("{} would be analyzed, {}", planner::count(count, "file", "files"), skips);
+
+    if !unsupported.is_empty()
+    {
+        println!("{}", unsupported);
+    }
+}
+
+/// The diagnostic for a generated or vendored file. The header line usually
+/// names an organization, so it is left out when anonymizing
+fn category_diagnostic(classified: &Classified, anonymized: bool) -> (Code, String)
+{
+    let (code, mut message) = match classified.category
+    {
+        Category::Generated => (Code::Generated, format!("file name matches {}", classified.reason)),
+        Category::Vendored if anonymized => (Code::Vendored, "license header names another project".to_string()),
+        Category::Vendored => (Code::Vendored, format!("license header names another project: {}", escape::strip_control(&classified.reason))),
+    };
+
+    if classified.mode == CategoryMode::Exclude
+    {
+        message.push_str(", left out");
+    }
+
+    (code, message)
+}
+
+/// Mean of two per-file means, weighted by their line counts
+fn weighted_mean(a: f64, a_nloc: u64, b: f64, b_nloc: u64) -> f64
+{
+    if a_nloc + b_nloc == 0
+    {
+        return (a + b) / 2.0;
+    }
+
+    (a * a_nloc as f64 + b * b_nloc as f64) / (a_nloc + b_nloc) as f64
+}
+
+/// Turn arbitrary text into a safe JS identifier. Anything other than ASCII
+/// alphanumerics, `_`, and `$` is replaced so the flag can never inject code
+/// into the generated file
+fn sanitize_identifier(name: &str) -> String
+{
+    const RESERVED: [&str; 38] = [
+        "break", "case", "catch", "class", "const", "continue", "debugger",
+        "default", "delete", "do", "else", "enum", "export", "extends", "false",
+        "finally", "for", "function", "if", "import", "in", "instanceof", "let",
+        "new", "null", "return", "static", "super", "switch", "this", "throw",
+        "true", "try", "typeof", "var", "void", "while", "with",
+    ];
+
+    let mut id: String = name.chars()
+                             .map(|c| if c.is_ascii_alphanumeric() || c == '_' || c == '$' { c } else { '_' })
+                             .collect();
+
+    if id.is_empty() || id.starts_with(|c: char| c.is_ascii_digit()) || RESERVED.contains(&id.as_str())
+    {
+        id.insert(0, '_');
+    }
+
+    id
 }
 
 fn main()
 {
-    let args = Args::parse();
+    let started = std::time::Instant::now();
+    let matches = Args::command().get_matches();
+    let mut args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
 
-    let walker = WalkDir::new(&args.path).into_iter();
+    let verbosity = match (args.quiet, args.verbose)
+    {
+        (true, _) => Verbosity::Quiet,
+        (false, 0) => Verbosity::Normal,
+        (false, 1) => Verbosity::Verbose,
+        (false, _) => Verbosity::Debug,
+    };
 
-    let mut nlocs = Vec::new();
-    let mut labels = Vec::new();
-    let mut parents = Vec::new();
-    let mut ccs = Vec::new();
+    /* made first, so every error from here on is a diagnostic */
+    let mut reporter = match Reporter::new(args.errors_format, args.errors_file.as_deref(), verbosity)
+    {
+        Ok(reporter) => reporter,
+        Err(e) => {
+            /* stderr can always be written */
+            let mut reporter = Reporter::new(args.errors_format, None, verbosity).unwrap();
+            reporter.emit(Diagnostic::new(Code::Write, args.errors_file.as_deref(), format!("could not be created: {}", e)));
+            drop(reporter);
+            std::process::exit(1);
+        }
+    };
 
-    /* TODO: multithreading. performance isn't a massive issue atm though */
-    /* parse each file and calculate complexity */
-    for entry in walker.filter_entry(|e| !file_parser::is_hidden(e))
+    let listed = match &args.files_from
     {
-        if file_parser::is_file_extension_valid(&entry.as_ref().unwrap()
-                                                      .file_name()
-                                                      .to_str().unwrap())
+        Some(source) => match files_from(source)
         {
-            let mut file = FileParser::new(&entry.as_ref().unwrap());
+            Ok(listed) => listed,
+            Err(e) => {
+                reporter.emit(Diagnostic::new(Code::FilesFromUnreadable, Some(source), format!("could not be read: {}", e)));
+                drop(reporter);
+                std::process::exit(1);
+            }
+        },
+        None => args.path.clone(),
+    };
+
+    let mut paths = Vec::new();
+    /* a list from git can name files that were since deleted, which shouldn't
+     * stop the rest being analyzed */
+    let mut unlisted = Vec::new();
 
-            match file.file_walk()
+    for path in &listed
+    {
+        match root::normalize(path)
+        {
+            Ok(path) => paths.push(path),
+            Err(e) if args.files_from.is_some() => unlisted.push(e),
+            Err(e) => {
+                reporter.emit(Diagnostic::new(Code::Path, None, e.to_string()));
+                drop(reporter);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    if paths.is_empty()
+    {
+        for e in &unlisted
+        {
+            reporter.emit(Diagnostic::new(Code::FilesFrom, None, format!("{}, left out", e)));
+        }
+
+        reporter.emit(Diagnostic::new(Code::FilesFromUnreadable, args.files_from.as_deref(),
+                                      format!("none of the {} paths listed could be analyzed", listed.len())));
+        drop(reporter);
+        std::process::exit(1);
+    }
+
+    /* with several paths the root is the directory above them all, which is
+     * what globs and relative paths are taken against. only the given paths
+     * are walked */
+    let (root, paths) = root::common_ancestor(&paths);
+
+    /* a single file is analyzed on its own, labelled with its name */
+    let single_file = root.is_file();
+
+    if single_file && !file_parser::is_file_extension_valid(&root.file_name().unwrap_or_default().to_string_lossy())
+    {
+        reporter.emit(Diagnostic::new(Code::Parse, Some(&root), "is not a file cyclo can parse"));
+        drop(reporter);
+        std::process::exit(1);
+    }
+
+    let config = match args.config.clone().or_else(|| config::discover(&root))
+    {
+        Some(path) => match config::load(&path)
+        {
+            Ok(config) => config,
+            Err(e) => {
+                reporter.emit(Diagnostic::new(Code::Config, None, e.to_string()));
+                drop(reporter);
+                std::process::exit(1);
+            }
+        },
+        None => config::Config::default(),
+    };
+
+    /* flags given on the command line or in the environment win over the file */
+    let given = |id: &str| matches!(matches.value_source(id), Some(ValueSource::CommandLine | ValueSource::EnvVariable));
+
+    if let Some(exclude) = config.exclude.clone().filter(|_| !given("exclude"))
+    {
+        args.exclude = exclude;
+    }
+
+    if let Some(include) = config.include.clone().filter(|_| !given("include"))
+    {
+        args.include = include;
+    }
+
+    if let Some(extensions) = config.extensions.clone().filter(|_| !given("extensions"))
+    {
+        args.extensions = Some(extensions);
+    }
+
+    if let Some(ignores) = config.default_ignores.clone().filter(|_| !given("default-ignores") && !given("no-default-ignores"))
+    {
+        args.default_ignores = Some(ignores);
+    }
+
+    if let Some(ignores) = config.extra_ignores.clone().filter(|_| !given("extra-ignores"))
+    {
+        args.extra_ignores = ignores;
+    }
+
+    if let Some(threshold) = config.fail_over.filter(|_| !given("fail-above"))
+    {
+        args.fail_above = Some(threshold);
+    }
+
+    if let Some(output) = config.output.clone().filter(|_| !given("output"))
+    {
+        args.output = Some(output);
+    }
+
+    if let Some(format) = config.format.filter(|_| !given("format"))
+    {
+        args.format = format;
+    }
+
+    if args.print_config
+    {
+        let origin = |id: &str, in_file: bool| match &config.path
+        {
+            _ if given(id) => "command line".to_string(),
+            Some(path) if in_file => root::display(path),
+            _ => "default".to_string(),
+        };
+        let strings = |list: &[String]| toml::Value::Array(list.iter().cloned().map(toml::Value::String).collect());
+        let format = args.format.to_possible_value().map(|v| v.get_name().to_string()).unwrap_or_default();
+
+        let settings = [("exclude", Some(strings(&args.exclude)), origin("exclude", config.exclude.is_some())),
+                        ("include", Some(strings(&args.include)), origin("include", config.include.is_some())),
+                        ("extensions", args.extensions.as_deref().map(strings), origin("extensions", config.extensions.is_some())),
+                        ("default_ignores", args.default_ignores.as_deref().map(strings), origin("default-ignores", config.default_ignores.is_some())),
+                        ("extra_ignores", Some(strings(&args.extra_ignores)), origin("extra-ignores", config.extra_ignores.is_some())),
+                        ("fail_over", args.fail_above.map(|t| toml::Value::String(t.to_string())), origin("fail-above", config.fail_over.is_some())),
+                        ("output", args.output.as_deref().map(|o| toml::Value::String(root::display(o))), origin("output", config.output.is_some())),
+                        ("format", Some(toml::Value::String(format)), origin("format", config.format.is_some()))];
+
+        print!("{}", config::render(&settings, &config.unknown));
+        return;
+    }
+
+    let mut plan_options = PlanOptions::default();
+
+    /* from the flag or the config file, `.c` and `c` both mean the same */
+    if let Some(extensions) = &args.extensions
+    {
+        plan_options.extensions = extensions.iter().map(|e| e.trim_start_matches('.').to_string()).collect();
+    }
+
+    if let Some(ignores) = &args.default_ignores
+    {
+        plan_options.default_ignores = ignores.clone();
+    }
+
+    if args.no_default_ignores
+    {
+        plan_options.default_ignores.clear();
+    }
+
+    for ignore in &args.extra_ignores
+    {
+        if !plan_options.default_ignores.contains(ignore)
+        {
+            plan_options.default_ignores.push(ignore.clone());
+        }
+    }
+
+    plan_options.gitignore = !args.no_ignore;
+
+    if !args.no_auto_exclude
+    {
+        /* markers may be in any of the paths as well as the root */
+        for path in std::iter::once(&root).chain(&paths)
+        {
+            for ignore in auto_exclude::detect(path)
             {
-                Ok(()) => {
-                    nlocs.push(file.nloc.unwrap());
-                    ccs.push(file.cc.unwrap());
-                    labels.push(file.label.unwrap().clone());
-                    parents.push(file.parent.unwrap().clone());
-                },
+                if !plan_options.auto_ignores.iter().any(|known| known.name == ignore.name)
+                {
+                    plan_options.auto_ignores.push(ignore);
+                }
+            }
+        }
+    }
+
+    for (flag, patterns, globs) in [("--exclude", &args.exclude, &mut plan_options.excludes),
+                                    ("--include", &args.include, &mut plan_options.includes)]
+    {
+        for pattern in patterns
+        {
+            match PathGlob::new(pattern)
+            {
+                Ok(glob) => globs.push(glob),
                 Err(e) => {
-                    eprintln!("Error: {:?}", e);
-                    continue;
+                    reporter.emit(Diagnostic::new(Code::Config, None, format!("invalid {} pattern: {}", flag, e)));
+                    drop(reporter);
+                    std::process::exit(1);
                 }
             }
+        }
+    }
+
+    let patterns = if args.generated_pattern.is_empty()
+    {
+        category::DEFAULT_GENERATED.iter().map(|p| Regex::new(p).unwrap()).collect()
+    }
+    else
+    {
+        args.generated_pattern.clone()
+    };
+
+    let classifier = Classifier
+    {
+        generated: args.detect_generated.map(|mode| (mode.unwrap_or(CategoryMode::Separate), patterns)),
+        vendored: args.detect_vendored.map(|mode| (mode.unwrap_or(CategoryMode::Include), args.our_copyright.clone())),
+    };
+
+    /* excluded categories are skipped by the planner, so a dry run lists them */
+    plan_options.classifier = classifier.clone();
+
+    let auto_ignores = plan_options.auto_ignores.clone();
+    /* kept for counting the files up front for the progress bar */
+    let count_options = plan_options.clone();
+    let planner = Planner::new(&root, &paths, plan_options);
+    /* each --path is a box of its own, however deep it is on disk. listed
+     * files keep their directories, from the one they are all in */
+    let labeler = match (&args.files_from, paths.len())
+    {
+        (None, 2..) => Labeler::synthetic(&paths, args.root_label.as_deref()),
+        _ => Labeler::new(&root, args.root_label.as_deref()),
+    };
+    let mut anonymizer = args.anonymize.map(|_| Anonymizer::new(args.root_label.is_some()));
+
+    /* a link to a hashed path goes nowhere, and the real one would give the
+     * name away */
+    let links = match (&args.link_template, &anonymizer)
+    {
+        (Some(template), None) => Some(LinkTemplate::new(template, args.link_rev.as_deref(), &root)),
+        _ => None,
+    };
+
+    let options = ParseOptions
+    {
+        count_global_init: args.count_global_init,
+        count_compile_time: args.count_compile_time,
+        macros_as_functions: args.macros_as_functions,
+        strict_utf8: args.strict_utf8,
+    };
+
+    if args.dry_run
+    {
+        dry_run(planner, &labeler, &mut anonymizer, args.format == Format::Json);
+        return;
+    }
+
+    let output = match (&args.output, args.format)
+    {
+        (Some(path), _) => Some(path.clone()),
+        /* outside a checkout, e.g. after `cargo install`, there is no webserver */
+        (None, Format::Js) if Path::new(WEBSERVER_OUTPUT).parent().is_some_and(Path::is_dir) => Some(PathBuf::from(WEBSERVER_OUTPUT)),
+        (None, Format::Js) => Some(PathBuf::from("cyclo.js")),
+        (None, _) => None,
+    };
+    let debug_path = match (&args.debug_path, &args.output)
+    {
+        (Some(path), _) => Some(path.clone()),
+        (None, Some(output)) if args.debug => Some(output.with_file_name("debug.txt")),
+        (None, None) if args.debug => Some(PathBuf::from("debug.txt")),
+        (None, _) => None,
+    };
+    #[cfg(feature = "sqlite")]
+    let sqlite_path = args.sqlite.clone();
+    #[cfg(not(feature = "sqlite"))]
+    let sqlite_path: Option<PathBuf> = None;
+
+    let destinations = [output.clone(), debug_path.clone(), sqlite_path, args.anonymize_map.clone()];
+
+    /* a record per file and directory, each directory after its first file */
+    let mut records: Vec<Record> = Vec::new();
+    /* label -> index in records, so finding a label doesn't scan them all.
+     * on huge trees that scan made adding directories quadratic */
+    let mut label_index: std::collections::HashMap<String, usize> = std::collections::HashMap::new();
+    let mut summaries: Vec<FileSummary> = Vec::new();
+    let mut folder = CaseFolder::default();
+
+    /* labels after rewriting, so a rewrite that maps two files onto one label
+     * is caught rather than silently merging them. each keeps how it got there
+     * for the error */
+    let mut rewritten_labels: std::collections::HashMap<String, Provenance> = std::collections::HashMap::new();
+    let (mut rewritten, mut unmatched, mut conflicts) = (0, 0, 0);
+
+    let mut unsupported = UnsupportedTally::default();
+    let mut skipped: u64 = 0;
+    /* labels of generated and vendored files, which --fail-above ignores */
+    let mut categorized = std::collections::HashSet::new();
+    /* --exclude pattern -> entries it left out */
+    let mut excluded: std::collections::HashMap<String, u64> = std::collections::HashMap::new();
+
+    /* fail before parsing anything rather than losing the run at the end */
+    for path in destinations.iter().flatten()
+    {
+        if let Err(e) = artifact::check_writable(path)
+        {
+            reporter.emit(Diagnostic::new(Code::Write, Some(&artifact::absolute(path)), format!("cannot be written: {}", e)));
+            /* exit doesn't run destructors, so flush the diagnostics first */
+            drop(reporter);
+            std::process::exit(1);
+        }
+    }
+
+    let mut checkpoint = match &args.checkpoint
+    {
+        Some(path) => match Checkpoint::open(path, &root, &options, args.resume)
+        {
+            Ok(checkpoint) => Some(checkpoint),
+            Err(e) => {
+                reporter.emit(Diagnostic::new(Code::Checkpoint, Some(path), format!("could not open: {}", e)));
+                drop(reporter);
+                std::process::exit(1);
+            }
+        },
+        None => None,
+    };
 
-            /* dumb to do this again but it works */
-            let depth = entry.as_ref().unwrap().depth();
-            let len = entry.as_ref().unwrap().path().to_str().unwrap()
-                           .split("/").count();
+    for e in &unlisted
+    {
+        reporter.emit(Diagnostic::new(Code::FilesFrom, None, format!("{}, left out", e)));
+    }
 
-            let mut full_path = entry.as_ref().unwrap().path().to_str().unwrap()
-                                     .split("/")
-                                     .collect::<Vec<&str>>();
+    for key in &config.unknown
+    {
+        reporter.emit(Diagnostic::new(Code::ConfigKey, config.path.as_deref(), format!("unknown key '{}' was ignored", key)));
+    }
 
-            /* pop to remove filename from path */
-            full_path.pop();
+    /* one note per marker, in the order the rules are checked */
+    let mut markers: Vec<&str> = Vec::new();
 
-            /* loop through and check if the parent dirs are in the parent and label vecs */
-            for _ in 0..depth
-            {
-                /* check if the path is a parent */
+    for ignore in &auto_ignores
+    {
+        if !markers.contains(&ignore.marker.as_str())
+        {
+            markers.push(&ignore.marker);
+        }
+    }
+
+    for marker in markers
+    {
+        let names: Vec<&str> = auto_ignores.iter()
+                                           .filter(|ignore| ignore.marker == marker)
+                                           .map(|ignore| ignore.name.as_str())
+                                           .collect();
+
+        reporter.emit(Diagnostic::new(Code::AutoExclude, None,
+                                      format!("auto-excluding {} because {} is present", names.join(", "), marker)));
+    }
+
+    /* a second walk, but of metadata only, and only when the bar is shown */
+    reporter.start_progress(|| Planner::new(&root, &paths, count_options).filter(|p| matches!(p, Planned::Analyze(_))).count() as u64);
+
+    /* TODO: multithreading. performance isn't a massive issue atm though */
+    /* parse each file and calculate complexity */
+    for planned in planner
+    {
+        let candidate = match planned
+        {
+            Planned::Analyze(candidate) => candidate,
+            Planned::Skip(skip) => {
+                let (code, message) = match (&skip.reason, classifier.classify(&skip.path))
+                {
+                    (SkipReason::Category(_), Some(classified)) => category_diagnostic(&classified, anonymizer.is_some()),
+                    _ => (Code::Skipped, skip.reason.to_string()),
+                };
+
+                reporter.emit(Diagnostic::new(code, Some(&shown_path(&mut anonymizer, &labeler, &skip.path)), message));
+                unsupported.record(&skip);
+                skipped += 1;
 
-                /* if the parent path does not exist in the parent vec */
-                if !labels.contains(&full_path[len-depth-1..].join("/"))
+                if let SkipReason::Exclude(pattern) = &skip.reason
                 {
-                    nlocs.push(0);
-                    ccs.push(0.0);
-                    labels.push(full_path[len-depth-1..].join("/"));
+                    *excluded.entry(pattern.clone()).or_insert(0) += 1;
+                }
+
+                continue;
+            }
+        };
+
+        let entry = candidate.entry;
+
+        /* by label, so the bar shows the same names as the chart */
+        let current = labeler.label(entry.path()).unwrap_or_default();
+        let current = match &mut anonymizer
+        {
+            Some(anonymizer) => anonymizer.label(&current),
+            None => current,
+        };
+        reporter.advance(&escape::middle_ellipsis(&current, 24));
+
+        let classified = classifier.classify(entry.path());
+
+        /* excluded ones were already skipped by the planner */
+        if let Some(classified) = &classified
+        {
+            let (code, message) = category_diagnostic(classified, anonymizer.is_some());
+
+            reporter.emit(Diagnostic::new(code, Some(&shown_path(&mut anonymizer, &labeler, entry.path())), message));
+        }
+
+        let branch = classified.as_ref()
+                               .filter(|c| c.mode == CategoryMode::Separate)
+                               .map(|c| c.category);
+
+        let mut file = FileParser::new(&entry, &labeler, &options);
+
+        /* where the file is found in the checkpoint, if it is being kept */
+        let checkpointed = checkpoint.as_ref().and_then(|_| {
+            let relative = entry.path().strip_prefix(&root).ok()?.to_string_lossy().into_owned();
+            Some((relative, checkpoint::fingerprint(entry.path())?))
+        });
+
+        let done = checkpoint.as_ref()
+                             .zip(checkpointed.as_ref())
+                             .and_then(|(checkpoint, (relative, fingerprint))| checkpoint.get(relative, fingerprint));
+
+        let result = match done
+        {
+            Some(done) => file.resume(done.nloc, done.cc, done.compile_time_cc, done.functions, done.macros.clone(), done.had_invalid_utf8),
+            None => {
+                let result = file.file_walk();
+
+                if let (Ok(()), Some(c), Some((relative, fingerprint))) = (&result, &mut checkpoint, &checkpointed)
+                {
+                    if let Err(e) = c.record(relative, fingerprint, &file)
+                    {
+                        reporter.emit(Diagnostic::new(Code::Checkpoint, args.checkpoint.as_deref(), format!("could not write: {}", e)));
+                        reporter.finish_progress();
+                        drop(reporter);
+                        std::process::exit(1);
+                    }
+                }
+
+                result
+            }
+        };
+
+        match result
+        {
+            Ok(()) => {
+                if file.skipped_macros > 0
+                {
+                    reporter.emit(Diagnostic::new(Code::MacroSkipped, Some(&shown_path(&mut anonymizer, &labeler, entry.path())),
+                                                  format!("{} macro bodies could not be parsed", file.skipped_macros)));
+                }
+
+                let mut raw_label = file.label.clone().unwrap();
+                let mut link_path = None;
 
-                    full_path.pop();
+                if !args.path_rewrite.is_empty()
+                {
+                    let mut provenance = Provenance::default();
+                    provenance.push("path", &root::display(entry.path()));
+                    provenance.push("label", &raw_label);
 
-                    if full_path.is_empty()
+                    match labels::rewrite(&args.path_rewrite, &raw_label)
                     {
-                        parents.push("".to_string());
+                        RewriteResult::Rewritten(label) => {
+                            rewritten += 1;
+                            provenance.push("--path-rewrite", &label);
+                            link_path = Some(label.clone());
+                            raw_label = label;
+                        },
+                        RewriteResult::Unmatched => unmatched += 1,
+                        RewriteResult::Conflict => conflicts += 1,
+                    }
 
+                    if let Some(first) = rewritten_labels.get(&raw_label)
+                    {
+                        let mut show = |step: &str, value: &str| match &mut anonymizer
+                        {
+                            Some(anonymizer) if step == "path" => root::display(&anonymizer.path(&labeler, Path::new(value))),
+                            Some(anonymizer) => anonymizer.label(value),
+                            None => value.to_string(),
+                        };
+                        let message = format!("rewritten to the same label as another file, so this one was left out: {} (first: {})",
+                                              provenance.shown(&mut show), first.shown(&mut show));
+
+                        reporter.emit(Diagnostic::new(Code::RewriteCollision, Some(&shown_path(&mut anonymizer, &labeler, entry.path())), message));
+                        continue;
                     }
-                    else
+
+                    rewritten_labels.insert(raw_label.clone(), provenance);
+                }
+
+                if let Some(category) = branch
+                {
+                    raw_label = category.branch_label(&raw_label);
+                }
+
+                let folded = folder.fold(&raw_label);
+                let label = if args.merge_case_collisions { folded } else { raw_label.clone() };
+
+                let (label, raw_label) = match &mut anonymizer
+                {
+                    Some(anonymizer) => (anonymizer.label(&label), anonymizer.label(&raw_label)),
+                    None => (label, raw_label),
+                };
+
+                let nloc = file.nloc.unwrap();
+                let cc = file.cc.unwrap();
+                let compile_time_cc = file.compile_time_cc.unwrap_or(0.0);
+
+                reporter.log(Verbosity::Verbose, &format!("{}: nloc {}, cc {:.2}", label, nloc, cc));
+
+                /* only possible when merging, since labels are otherwise unique */
+                if let Some(&i) = label_index.get(&label)
+                {
+                    let record = &mut records[i];
+
+                    record.cc = weighted_mean(record.cc, record.nloc, cc, nloc);
+                    record.compile_time_cc = weighted_mean(record.compile_time_cc, record.nloc, compile_time_cc, nloc);
+                    record.nloc += nloc;
+                    record.had_invalid_utf8 |= file.had_invalid_utf8;
+                    record.functions.extend(file.macros);
+                    record.function_count += file.functions;
+
+                    if !record.hovertext.is_empty()
                     {
-                        parents.push(full_path[len-depth-1..].join("/"));
+                        record.hovertext.push_str("<br>");
                     }
+                    record.hovertext.push_str(&format!("merged with {}", escape::html(&escape::strip_control(&raw_label))));
+
+                    if let Some(summary) = summaries.iter_mut().find(|s| s.label == label)
+                    {
+                        summary.nloc = record.nloc;
+                        summary.cc = record.cc;
+                    }
+
+                    continue;
                 }
+
+                let link = links.as_ref()
+                                .and_then(|links| match &link_path
+                                {
+                                    Some(path) => links.url_for(path, 1),
+                                    None => links.url(entry.path().strip_prefix(&root).ok()?, 1),
+                                });
+                let mut hovertext = link.as_ref()
+                                        .map(|url| format!("<a href=\"{}\">view source</a>", escape::html(url)))
+                                        .unwrap_or_default();
+
+                if let Some(classified) = &classified
+                {
+                    if !hovertext.is_empty()
+                    {
+                        hovertext.push_str("<br>");
+                    }
+
+                    hovertext.push_str(classified.category.name());
+
+                    if classified.category == Category::Vendored && anonymizer.is_none()
+                    {
+                        hovertext.push_str(&format!(": {}", escape::html(&escape::strip_control(&classified.reason))));
+                    }
+                }
+
+                summaries.push(FileSummary
+                {
+                    label: label.clone(),
+                    nloc,
+                    cc,
+                    url: link,
+                });
+
+                if classified.is_some()
+                {
+                    categorized.insert(label.clone());
+                }
+
+                label_index.insert(label.clone(), records.len());
+                records.push(Record
+                {
+                    is_dir: false,
+                    parent: labels::parent_label(&label).to_string(),
+                    label,
+                    nloc,
+                    cc,
+                    compile_time_cc,
+                    function_count: file.functions,
+                    functions: file.macros,
+                    had_invalid_utf8: file.had_invalid_utf8,
+                    hovertext,
+                });
+            },
+            Err(e) => {
+                let code = match e
+                {
+                    FileParserError::Unreadable { .. } => Code::Io,
+                    FileParserError::BadFileExtension { .. } => Code::Parse,
+                    FileParserError::OutsideRoot { .. } => Code::Path,
+                    FileParserError::InvalidUtf8 { .. } => Code::InvalidUtf8,
+                };
+
+                let message = match anonymizer
+                {
+                    Some(_) => e.to_string().replace(&file.filename, &anonymize::token(&file.filename)),
+                    None => e.to_string(),
+                };
+
+                reporter.emit(Diagnostic::new(code, Some(&shown_path(&mut anonymizer, &labeler, entry.path())), message));
+                continue;
             }
         }
+
+        /* walk up from the file's directory adding any directories that aren't in
+         * the label vec yet. once one is found all of its ancestors are too */
+        let mut dir_label = records.last().unwrap().parent.clone();
+        /* category branches have no directory of their own, so their labels
+         * no longer line up with the paths for READMEs */
+        let mut dir_path = if branch.is_some() { None } else { entry.path().parent() };
+
+        while !dir_label.is_empty() && !label_index.contains_key(&dir_label)
+        {
+            let readme = if args.with_readmes && !labeler.is_synthetic(&dir_label)
+            {
+                dir_path.and_then(readme::excerpt)
+            }
+            else
+            {
+                None
+            };
+            let parent = labels::parent_label(&dir_label).to_string();
+
+            label_index.insert(dir_label.clone(), records.len());
+            records.push(Record
+            {
+                is_dir: true,
+                label: dir_label,
+                parent: parent.clone(),
+                nloc: 0,
+                cc: 0.0,
+                compile_time_cc: 0.0,
+                function_count: 0,
+                functions: Vec::new(),
+                had_invalid_utf8: false,
+                hovertext: readme.unwrap_or_default(),
+            });
+
+            dir_label = parent;
+            dir_path = dir_path.and_then(Path::parent);
+        }
+    }
+
+    reporter.finish_progress();
+
+    let chart_id = match &args.chart_id
+    {
+        None => "jsondata".to_string(),
+        Some(Some(id)) => sanitize_identifier(id),
+        Some(None) => {
+            let name = root.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+
+            if anonymizer.is_some()
+            {
+                sanitize_identifier(&anonymize::token(&name))
+            }
+            else
+            {
+                sanitize_identifier(&name)
+            }
+        }
+    };
+
+    if !unsupported.is_empty()
+    {
+        reporter.emit(Diagnostic::new(Code::Unsupported, None, unsupported.to_string()));
     }
 
-    /* test lengths of the vecs, since they must all be the same */
-    assert_eq!(nlocs.len(), labels.len(), "nloc ({}) and label ({}) vector length equality failed", nlocs.len(), labels.len());
-    assert_eq!(labels.len(), parents.len(), "labels ({}) and parents ({}) vector length equality failed", labels.len(), parents.len());
-    assert_eq!(parents.len(), ccs.len(), "parents ({}) and ccs ({}) vector lengthe equality failed", parents.len(), ccs.len());
+    if !args.exclude.is_empty()
+    {
+        let counts: Vec<String> = args.exclude.iter()
+                                              .map(|pattern| format!("'{}': {}", pattern, excluded.get(pattern).unwrap_or(&0)))
+                                              .collect();
 
+        reporter.emit(Diagnostic::new(Code::Excluded, None,
+                                      format!("excluded {} entries ({})", excluded.values().sum::<u64>(), counts.join(", "))));
 
-    /* write the js file */
+        for pattern in args.exclude.iter().filter(|pattern| !excluded.contains_key(*pattern))
+        {
+            reporter.emit(Diagnostic::new(Code::ExcludeUnmatched, None, format!("--exclude '{}' matched nothing", pattern)));
+        }
+    }
+
+    if unmatched + conflicts > 0
     {
-        let sum = ccs.iter().sum::<f64>();
-        let count = ccs.len();
+        reporter.emit(Diagnostic::new(Code::RewriteUnmatched, None,
+                                      format!("{} labels rewritten, {} matched no --path-rewrite, {} matched rewrites that disagree and were left as is",
+                                              rewritten, unmatched, conflicts)));
+    }
+
+    for collision in folder.collisions()
+    {
+        let spellings: Vec<String> = collision.iter()
+                                              .map(|label| match &mut anonymizer
+                                              {
+                                                  Some(anonymizer) => anonymizer.label(label),
+                                                  None => label.clone(),
+                                              })
+                                              .collect();
+        let action = if args.merge_case_collisions { "merged" } else { "pass --merge-case-collisions to merge them" };
+
+        reporter.emit(Diagnostic::new(Code::CaseCollision, None,
+                                      format!("labels differ only in case: {} ({})", spellings.join(", "), action)));
+    }
+
+    /* the limit is resolved before anything is written so it can be recorded
+     * with the run */
+    let gated: Vec<&Record> = records.iter()
+                                     .filter(|r| !r.is_dir && !categorized.contains(&r.label))
+                                     .collect();
+
+    let gate = args.fail_above.map(|threshold| {
+        let ccs: Vec<f64> = gated.iter().map(|r| r.cc).collect();
+        let limit = threshold.resolve(&ccs);
+        let failed: Vec<&Record> = gated.iter().copied().filter(|r| r.cc > limit).collect();
 
-        assert!(count > 0, "count ({}) is not greater than zero", count);
+        (threshold, limit, failed)
+    });
 
-        let mean = sum / count as f64;
+    /* the directory and git names would give the project away when anonymizing */
+    let header = (args.format == Format::Js).then(|| {
+        let top = records.iter().find(|r| r.parent.is_empty()).map(|r| r.label.clone()).unwrap_or_default();
 
-        let js_file = format!(r#"
-var jsondata = [{{
+        let subtitle = args.subtitle.clone().unwrap_or_else(|| {
+            let mut parts = Vec::new();
+
+            if anonymizer.is_none()
+            {
+                /* the name rather than the whole path, which is often a
+                 * temporary checkout */
+                parts.push(root.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_else(|| root::display(&root)));
+
+                let branch = links::git(&root, &["rev-parse", "--abbrev-ref", "HEAD"]);
+                let commit = links::git(&root, &["rev-parse", "--short", "HEAD"]);
+
+                match (branch, commit)
+                {
+                    (Some(branch), Some(commit)) => parts.push(format!("{} @ {}", branch, commit)),
+                    (None, Some(commit)) => parts.push(commit),
+                    _ => {},
+                }
+            }
+
+            if !args.no_timestamps
+            {
+                parts.push(header::utc(header::now()));
+            }
+
+            parts.push(format!("cyclo {}", env!("CARGO_PKG_VERSION")));
+
+            parts.join(" · ")
+        });
+
+        Header { title: args.title.clone().unwrap_or(top), subtitle }
+    });
+
+    /* write the report */
+    {
+        let contents = match args.format
+        {
+            Format::Js if args.chart == Chart::Matrix => matrix::render(&records, args.rollup_depth, &chart_id),
+            Format::Js => {
+            let color = |r: &Record| match args.color_by
+            {
+                ColorBy::Cc => r.cc,
+                ColorBy::CompileTime => r.compile_time_cc,
+            };
+            let colors: Vec<f64> = records.iter().map(color).collect();
+
+            /* directories are zero, which would drag the scale down */
+            let file_colors: Vec<f64> = records.iter()
+                                               .filter(|r| !r.is_dir)
+                                               .map(color)
+                                               .collect();
+
+            let color_spec = color_scale::prepare(&file_colors, &ColorOptions::default());
+
+            let nlocs: Vec<u64> = records.iter().map(|r| r.nloc).collect();
+            let labels: Vec<String> = records.iter().map(|r| r.label.clone()).collect();
+            let parents: Vec<String> = records.iter().map(|r| r.parent.clone()).collect();
+
+            /* full paths are the ids that parents refer to, while the shown labels
+             * are bounded. the full path goes in the hover text so nothing is lost */
+            let display_labels: Vec<String> = labels.iter()
+                                                    .map(|l| escape::html(&escape::strip_control(&escape::middle_ellipsis(l, args.max_label_len))))
+                                                    .collect();
+
+            /* files over --fail-above, flagged so they stand out whatever the
+             * colors say */
+            let violations: std::collections::HashSet<&str> = gate.iter()
+                                                                  .flat_map(|(_, _, failed)| failed.iter().map(|r| r.label.as_str()))
+                                                                  .collect();
+
+            let hovertext: Vec<String> = records.iter()
+                                                .map(|r| {
+                                                    let warning = if violations.contains(r.label.as_str()) { "⚠ " } else { "" };
+                                                    let label = format!("{}{}", warning, escape::html(&escape::strip_control(&r.label)));
+
+                                                    if r.hovertext.is_empty()
+                                                    {
+                                                        label
+                                                    }
+                                                    else
+                                                    {
+                                                        format!("{}<br>{}", label, r.hovertext)
+                                                    }
+                                                })
+                                                .collect();
+
+            /* only written with a limit, so the output is unchanged without one */
+            let line = match &gate
+            {
+                Some(_) => {
+                    let (colors, widths): (Vec<String>, Vec<u32>) = labels.iter()
+                                                                          .map(|label| if violations.contains(label.as_str())
+                                                                          {
+                                                                              ("#d7301f".to_string(), 4)
+                                                                          }
+                                                                          else
+                                                                          {
+                                                                              ("white".to_string(), 1)
+                                                                          })
+                                                                          .unzip();
+
+                    format!(", line: {{color: {}, width: {:?}}}", escape::json_string_array(&colors), widths)
+                },
+                None => String::new(),
+            };
+
+            let js_file = format!(r#"
+var {} = [{{
         type: "treemap",
         values: {:?},
-        labels: {:?},
-        parents: {:?},
-        marker: {{colors: {:.2?}, cmid: {:.2?}, colorscale: "Greens"}}
+        ids: {},
+        labels: {},
+        parents: {},
+        hovertext: {},
+        marker: {{colors: {:.2?}, cmin: {:.2?}, cmax: {:.2?}, colorscale: {}{}}}
 }}]
-    "#, nlocs, labels, parents, ccs, mean);
+    "#, chart_id, nlocs,
+            escape::json_string_array(&labels),
+            escape::json_string_array(&display_labels),
+            escape::json_string_array(&parents),
+            escape::json_string_array(&hovertext),
+            colors, color_spec.cmin, color_spec.cmax, escape::json_string(&color_spec.scale), line);
+
+                js_file
+            },
+            Format::Json => report::json(&report::Metadata { unsupported: unsupported.breakdown() }, &records),
+            Format::Csv => csv::render(&records),
+            /* the root path would give the names away */
+            Format::Sarif => {
+                let base = if single_file { root.parent().unwrap_or(&root) } else { &root };
+                let root_uri = anonymizer.is_none().then(|| sarif::root_uri(base));
+
+                sarif::render(&records, root_uri.as_deref(), args.threshold, args.max_findings)
+            },
+            Format::Markdown => markdown::render(&records, args.top),
+            Format::Cccc => cccc::render(&records),
+            Format::Codeclimate => codeclimate::render(&records, args.threshold, args.max_findings),
+            Format::Dot => dot::render(&records, args.dot_depth),
+        };
+
+        /* the same for either chart, after the chart's own variable */
+        let contents = match &header
+        {
+            Some(header) => format!("{}\n{}", contents.trim_end(), header.js(&chart_id)),
+            None => contents,
+        };
+
+        let artifact_options = ArtifactOptions
+        {
+            compress: args.compress_output.map(|mode| mode.unwrap_or(CompressMode::Alongside)),
+            checksum: args.checksum,
+        };
+
+        match &output
+        {
+            Some(path) => {
+                if let Err(e) = artifact::write(path, contents.as_bytes(), &artifact_options)
+                {
+                    let message = match artifact::rescue(path, contents.as_bytes())
+                    {
+                        Ok(rescued) => format!("could not be written: {}, the report was saved to {} instead", e, rescued.display()),
+                        Err(rescue_error) => format!("could not be written: {}, nor saved anywhere else: {}", e, rescue_error),
+                    };
+
+                    reporter.emit(Diagnostic::new(Code::Write, Some(&artifact::absolute(path)), message));
+                    drop(reporter);
+                    std::process::exit(1);
+                }
+            },
+            None => io::stdout().write_all(contents.as_bytes()).unwrap(),
+        }
+
+        /* the numbers are what matters when iterating on one file. they go to
+         * stderr when the report itself is on stdout */
+        if let (true, Some(record)) = (single_file, records.first())
+        {
+            let summary = format!("{}: nloc {}, cc {:.2}, functions {}", record.label, record.nloc, record.cc, record.function_count);
+
+            if output.is_none()
+            {
+                reporter.log(Verbosity::Normal, &summary);
+            }
+            else if verbosity > Verbosity::Quiet
+            {
+                println!("{}", summary);
+            }
+        }
+        else
+        {
+            let mut total = Rollup::default();
+
+            for record in records.iter().filter(|r| !r.is_dir)
+            {
+                total.add(record);
+            }
+
+            let written = output.as_deref().map(|path| format!(", wrote {}", root::display(path))).unwrap_or_default();
 
-        fs::write("../webserver/web/scripts/cyclo.js", js_file).unwrap();
+            reporter.log(Verbosity::Normal, &format!("analyzed {}, {} nloc, mean cc {:.2}, skipped {} in {:.1}s{}",
+                                                     planner::count(total.files, "file", "files"), total.nloc, total.mean_cc(),
+                                                     planner::count(skipped, "entry", "entries"), started.elapsed().as_secs_f64(), written));
+        }
     }
 
+    #[cfg(feature = "sqlite")]
+    if let Some(path) = &args.sqlite
+    {
+        /* the flags that change the numbers */
+        let flags = [(args.count_global_init, "--count-global-init"),
+                     (args.count_compile_time, "--count-compile-time"),
+                     (args.macros_as_functions, "--macros-as-functions"),
+                     (args.strict_utf8, "--strict-utf8"),
+                     (args.merge_case_collisions, "--merge-case-collisions")];
+        let mut options: Vec<String> = flags.iter()
+                                            .filter(|(set, _)| *set)
+                                            .map(|(_, flag)| flag.to_string())
+                                            .collect();
+
+        if let Some((threshold, limit, _)) = &gate
+        {
+            options.push(format!("--fail-above {} (resolved to {:.4})", threshold, limit));
+        }
+
+        /* an anonymized run is recorded under its top label */
+        let shown_root = match &anonymizer
+        {
+            Some(_) => records.iter().find(|r| r.parent.is_empty()).map(|r| r.label.clone()).unwrap_or_default(),
+            None => root::display(&root),
+        };
+        let git_commit = links::git(&root, &["rev-parse", "HEAD"]);
+
+        let timestamp = if args.no_timestamps { String::new() } else { header::iso8601(header::now()) };
 
-    if args.debug
+        let run = sqlite::Run
+        {
+            timestamp: &timestamp,
+            root: &shown_root,
+            options: &options.join(" "),
+            git_commit: git_commit.as_deref(),
+        };
+
+        if let Err(e) = sqlite::append(path, &run, &records)
+        {
+            reporter.emit(Diagnostic::new(Code::Write, None, e.to_string()));
+            drop(reporter);
+            std::process::exit(1);
+        }
+    }
+
+    if let (Some(anonymizer), Some(path)) = (&anonymizer, &args.anonymize_map)
     {
-        /* write the debug file */
-        let mut buffer = fs::File::create("debug.txt").unwrap();
+        if let Err(e) = anonymizer.write_map(path)
+        {
+            reporter.emit(Diagnostic::new(Code::Write, Some(&artifact::absolute(path)), format!("could not be written: {}", e)));
+            drop(reporter);
+            std::process::exit(1);
+        }
+    }
+
 
-        for i in 0..nlocs.len()
+    if let Some(path) = &debug_path
+    {
+        if let Err(e) = artifact::write(path, report::debug(&records).as_bytes(), &ArtifactOptions::default())
         {
-            writeln!(&mut buffer, "file: {:?}, nloc: {:?}, cc: {:?}", labels[i], nlocs[i], ccs[i]).unwrap();
+            reporter.emit(Diagnostic::new(Code::Write, Some(&artifact::absolute(path)), format!("could not be written: {}", e)));
+            drop(reporter);
+            std::process::exit(1);
         }
     }
+
+    if let Some(url) = &args.webhook_url
+    {
+        let webhook_options = WebhookOptions
+        {
+            url: url.clone(),
+            timeout: Duration::from_secs(args.webhook_timeout),
+            secret: args.webhook_secret.clone(),
+        };
+
+        /* the top node is the only one without a parent */
+        let root_label = records.iter()
+                                .find(|r| r.parent.is_empty())
+                                .map(|r| r.label.as_str())
+                                .unwrap_or_default();
+
+        if let Err(e) = webhook::post(&webhook_options, &webhook::payload(root_label, &summaries))
+        {
+            let code = if args.webhook_required { Code::WebhookRequired } else { Code::Webhook };
+
+            reporter.emit(Diagnostic::new(code, None, e.to_string()));
+
+            if args.webhook_required
+            {
+                /* exit doesn't run destructors, so flush the diagnostics first */
+                drop(reporter);
+                std::process::exit(1);
+            }
+        }
+    }
+    let mut gate_failed = false;
+
+    if let Some((threshold, limit, failed)) = &gate
+    {
+        for record in failed
+        {
+            reporter.emit(Diagnostic::new(Code::FailAbove, Some(Path::new(&record.label)),
+                                          format!("mean cc {:.2} is above {:.2} (--fail-above {})", record.cc, limit, threshold)));
+        }
+
+        if !failed.is_empty()
+        {
+            reporter.emit(Diagnostic::new(Code::Gate, None,
+                                          format!("{} {} above --fail-above {}, resolved to {:.4}",
+                                                  failed.len(), if failed.len() == 1 { "file is" } else { "files are" }, threshold, limit)));
+            gate_failed = true;
+        }
+    }
+
+    if let Some(limit) = args.fail_over_mean
+    {
+        let mut total = Rollup::default();
+
+        for record in &gated
+        {
+            total.add(record);
+        }
+
+        if total.mean_cc() > limit
+        {
+            reporter.emit(Diagnostic::new(Code::FailAbove, None,
+                                          format!("mean cc {:.2} over {} files is above {} (--fail-over-mean)", total.mean_cc(), total.files, limit)));
+            gate_failed = true;
+        }
+    }
+
+    if gate_failed
+    {
+        /* the outputs are already written, so the report is there to look at */
+        drop(reporter);
+        std::process::exit(EXIT_THRESHOLD);
+    }
 }
@@ -0,0 +1,146 @@
+use std::collections::BTreeMap;
+
+use crate::escape::strip_control;
+use crate::report::{self, Record, Rollup};
+
+
+/// Escape text for a table cell, so names with `|` or markdown syntax in them
+/// are shown as they are
+fn cell(s: &str) -> String
+{
+    let mut out = String::with_capacity(s.len());
+
+    for c in strip_control(s).chars()
+    {
+        match c
+        {
+            '\\' | '|' | '*' | '_' | '`' | '[' | ']' => {
+                out.push('\\');
+                out.push(c);
+            },
+            '<' => out.push_str("&lt;"),
+            c => out.push(c),
+        }
+    }
+
+    out
+}
+
+/// Render a readable summary for wikis and pull requests: totals, the `top`
+/// most complex files, and a rollup per top-level directory. Paths are
+/// relative to the analyzed root
+pub fn render(records: &[Record], top: usize) -> String
+{
+    let files: Vec<&Record> = records.iter().filter(|r| !r.is_dir).collect();
+
+    let mut total = Rollup::default();
+    let mut directories: BTreeMap<&str, Rollup> = BTreeMap::new();
+
+    for file in &files
+    {
+        total.add(file);
+
+        directories.entry(report::directory(file.relative_path(), 1)).or_default().add(file);
+    }
+
+    let root = records.iter().find(|r| r.parent.is_empty()).map(|r| r.label.as_str()).unwrap_or_default();
+
+    let mut out = format!("# Complexity of {}\n\n", cell(root));
+
+    out.push_str(&format!("- files analyzed: {}\n- total nloc: {}\n- total cc: {:.0}\n- mean cc: {:.2}\n",
+                          total.files, total.nloc, total.cc, total.mean_cc()));
+
+    /* worst first, ties broken by path so the report is stable */
+    let mut worst = files.clone();
+    worst.sort_by(|a, b| b.cc.total_cmp(&a.cc).then(a.label.cmp(&b.label)));
+
+    out.push_str("\n## Most complex files\n\n| file | nloc | mean cc |\n| --- | ---: | ---: |\n");
+
+    for file in worst.iter().take(top)
+    {
+        out.push_str(&format!("| {} | {} | {:.2} |\n", cell(file.relative_path()), file.nloc, file.cc));
+    }
+
+    out.push_str("\n## Directories\n\n| directory | files | nloc | total cc | mean cc |\n| --- | ---: | ---: | ---: | ---: |\n");
+
+    for (directory, rollup) in &directories
+    {
+        out.push_str(&format!("| {} | {} | {} | {:.0} | {:.2} |\n",
+                              cell(directory), rollup.files, rollup.nloc, rollup.cc, rollup.mean_cc()));
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn record(label: &str, is_dir: bool, nloc: u64, cc: f64, function_count: u64) -> Record
+    {
+        Record
+        {
+            is_dir,
+            label: label.to_string(),
+            parent: label.rsplit_once('/').map(|(parent, _)| parent.to_string()).unwrap_or_default(),
+            nloc,
+            cc,
+            compile_time_cc: 0.0,
+            function_count,
+            functions: Vec::new(),
+            had_invalid_utf8: false,
+            hovertext: String::new(),
+        }
+    }
+
+    #[test]
+    fn summary_of_a_small_project()
+    {
+        let records = [record("test/main.c", false, 40, 3.0, 2),
+                       record("test", true, 0, 0.0, 0),
+                       record("test/net/tcp.c", false, 100, 5.0, 4),
+                       record("test/net", true, 0, 0.0, 0),
+                       record("test/net/udp.c", false, 50, 2.0, 3),
+                       record("test/x_[y]|z.c", false, 10, 4.0, 1)];
+
+        assert_eq!(render(&records, 3), "\
+# Complexity of test
+
+- files analyzed: 4
+- total nloc: 200
+- total cc: 36
+- mean cc: 3.60
+
+## Most complex files
+
+| file | nloc | mean cc |
+| --- | ---: | ---: |
+| net/tcp.c | 100 | 5.00 |
+| x\\_\\[y\\]\\|z.c | 10 | 4.00 |
+| main.c | 40 | 3.00 |
+
+## Directories
+
+| directory | files | nloc | total cc | mean cc |
+| --- | ---: | ---: | ---: | ---: |
+| . | 2 | 50 | 10 | 3.33 |
+| net | 2 | 150 | 26 | 3.71 |
+");
+    }
+
+    #[test]
+    fn ties_are_ordered_by_path()
+    {
+        let records = [record("t/b.c", false, 1, 2.0, 1), record("t/a.c", false, 1, 2.0, 1), record("t", true, 0, 0.0, 0)];
+        let out = render(&records, 10);
+
+        assert!(out.find("| a.c |").unwrap() < out.find("| b.c |").unwrap(), "{}", out);
+    }
+
+    #[test]
+    fn cells_escape_markdown()
+    {
+        assert_eq!(cell("a*b_c`d\\e<f>\tg"), "a\\*b\\_c\\`d\\\\e&lt;f>?g");
+    }
+}
@@ -0,0 +1,162 @@
+use std::collections::BTreeMap;
+
+use crate::color_scale::{self, ColorOptions};
+use crate::escape::{html, json_string, json_string_array, strip_control};
+use crate::report::{self, Record, Rollup};
+
+
+/// Column names, in the order they are shown
+const METRICS: [&str; 4] = ["nloc", "total cc", "mean cc", "worst file cc"];
+
+/// The value of each metric for a directory
+fn metrics(rollup: &Rollup) -> [f64; 4]
+{
+    [rollup.nloc as f64, rollup.cc, rollup.mean_cc(), rollup.worst]
+}
+
+/// Scale a value to 0..1 between the lowest and highest of its column, so
+/// metrics in different units share one color scale. A column where every
+/// value is the same is all zero
+fn normalize(value: f64, min: f64, max: f64) -> f64
+{
+    if max > min { (value - min) / (max - min) } else { 0.0 }
+}
+
+/// Render a plotly heatmap of directories against metrics as the JS the
+/// webserver loads. Rows are the directories `depth` levels below the root,
+/// and each column is colored on its own scale. The hover text has the raw
+/// and the normalized value
+pub fn render(records: &[Record], depth: usize, chart_id: &str) -> String
+{
+    let mut directories: BTreeMap<&str, Rollup> = BTreeMap::new();
+
+    for file in records.iter().filter(|r| !r.is_dir)
+    {
+        directories.entry(report::directory(file.relative_path(), depth)).or_default().add(file);
+    }
+
+    let rows: Vec<[f64; 4]> = directories.values().map(metrics).collect();
+
+    let mut bounds = [(f64::INFINITY, f64::NEG_INFINITY); 4];
+
+    for row in &rows
+    {
+        for (bound, value) in bounds.iter_mut().zip(row)
+        {
+            *bound = (bound.0.min(*value), bound.1.max(*value));
+        }
+    }
+
+    let mut z = Vec::new();
+    let mut cells = Vec::new();
+    let mut text = Vec::new();
+
+    for (directory, row) in directories.keys().zip(&rows)
+    {
+        let normalized: Vec<f64> = row.iter()
+                                      .zip(&bounds)
+                                      .map(|(value, (min, max))| normalize(*value, *min, *max))
+                                      .collect();
+
+        let hover: Vec<String> = METRICS.iter()
+                                        .zip(row)
+                                        .zip(&normalized)
+                                        .map(|((metric, value), normalized)| {
+                                            /* rounded rather than padded, so counts show as whole numbers */
+                                            let raw = (value * 100.0).round() / 100.0;
+
+                                            format!("{}<br>{}: {} (normalized {:.2})", html(&strip_control(directory)), metric, raw, normalized)
+                                        })
+                                        .collect();
+
+        z.push(format!("{:.4?}", normalized));
+        cells.extend(normalized);
+        text.push(json_string_array(&hover));
+    }
+
+    /* the same domain as the treemap, taken over the normalized cells */
+    let color_spec = color_scale::prepare(&cells, &ColorOptions::default());
+
+    let x: Vec<String> = METRICS.iter().map(|m| m.to_string()).collect();
+    let y: Vec<String> = directories.keys().map(|d| html(&strip_control(d))).collect();
+
+    format!(r#"
+var {} = [{{
+        type: "heatmap",
+        x: {},
+        y: {},
+        z: [{}],
+        text: [{}],
+        hoverinfo: "text",
+        zmin: {:.2?},
+        zmax: {:.2?},
+        colorscale: {}
+}}]
+    "#, chart_id,
+            json_string_array(&x),
+            json_string_array(&y),
+            z.join(", "),
+            text.join(", "),
+            color_spec.cmin,
+            color_spec.cmax,
+            json_string(&color_spec.scale))
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn file(label: &str, nloc: u64, cc: f64, function_count: u64) -> Record
+    {
+        Record
+        {
+            is_dir: false,
+            label: label.to_string(),
+            parent: label.rsplit_once('/').map(|(parent, _)| parent.to_string()).unwrap_or_default(),
+            nloc,
+            cc,
+            compile_time_cc: 0.0,
+            function_count,
+            functions: Vec::new(),
+            had_invalid_utf8: false,
+            hovertext: String::new(),
+        }
+    }
+
+    #[test]
+    fn golden_trace()
+    {
+        let records = [file("test/main.c", 40, 3.0, 2), file("test/net/tcp.c", 100, 5.0, 4), file("test/net/udp.c", 50, 2.0, 3), file("test/ui/x.c", 95, 1.5, 2)];
+
+        assert_eq!(render(&records, 1, "jsondata"), r#"
+var jsondata = [{
+        type: "heatmap",
+        x: ["nloc", "total cc", "mean cc", "worst file cc"],
+        y: [".", "net", "ui"],
+        z: [[0.0000, 0.1304, 0.6774, 0.4286], [1.0000, 1.0000, 1.0000, 1.0000], [0.5000, 0.0000, 0.0000, 0.0000]],
+        text: [[".<br>nloc: 40 (normalized 0.00)", ".<br>total cc: 6 (normalized 0.13)", ".<br>mean cc: 3 (normalized 0.68)", ".<br>worst file cc: 3 (normalized 0.43)"], ["net<br>nloc: 150 (normalized 1.00)", "net<br>total cc: 26 (normalized 1.00)", "net<br>mean cc: 3.71 (normalized 1.00)", "net<br>worst file cc: 5 (normalized 1.00)"], ["ui<br>nloc: 95 (normalized 0.50)", "ui<br>total cc: 3 (normalized 0.00)", "ui<br>mean cc: 1.5 (normalized 0.00)", "ui<br>worst file cc: 1.5 (normalized 0.00)"]],
+        hoverinfo: "text",
+        zmin: -0.04,
+        zmax: 1.00,
+        colorscale: "Greens"
+}]
+    "#);
+    }
+
+    #[test]
+    fn equal_columns_are_zero()
+    {
+        assert_eq!(normalize(4.0, 4.0, 4.0), 0.0);
+        assert_eq!(normalize(3.0, 2.0, 4.0), 0.5);
+    }
+
+    #[test]
+    fn names_are_escaped_for_the_hover_html()
+    {
+        let out = render(&[file("test/<b>/x.c", 1, 1.0, 1)], 1, "matrix");
+
+        assert!(out.contains(r#"y: ["&lt;b&gt;"]"#), "{}", out);
+        assert!(out.contains(r#""&lt;b&gt;<br>nloc: 1 (normalized 0.00)""#), "{}", out);
+    }
+}
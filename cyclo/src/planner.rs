@@ -0,0 +1,702 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+use globset::{GlobBuilder, GlobMatcher};
+use serde::Serialize;
+use walkdir::{DirEntry, WalkDir};
+
+use crate::auto_exclude::AutoIgnore;
+use crate::category::{Category, Classifier};
+use crate::file_parser;
+use crate::gitignore::GitIgnores;
+
+
+/// Names that are skipped during the walk unless the user changes the set
+pub const DEFAULT_IGNORES: [&str; 6] = [".git", ".hg", ".svn", "node_modules", "target", "build"];
+
+/// Why an entry found during the walk is not going to be analyzed
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SkipReason
+{
+    /// Name matched a rule in the default ignore set. Ignored directories are
+    /// not descended into
+    DefaultIgnore(String),
+    /// Directory name skipped because a marker file at the root shows which
+    /// ecosystem the project uses, e.g. `node_modules` for `package.json`
+    AutoExclude { name: String, marker: String },
+    /// Path matched a pattern in a `.gitignore` or `.git/info/exclude`.
+    /// Ignored directories are not descended into
+    GitIgnore(String),
+    /// File that matched none of the `--include` patterns
+    NotIncluded,
+    /// Path matched an `--exclude` pattern. Excluded directories are not
+    /// descended into
+    Exclude(String),
+    /// File with an extension that cannot be parsed
+    UnsupportedExtension,
+    /// File put in a category left out with `--detect-generated exclude` or
+    /// `--detect-vendored exclude`
+    Category(Category),
+    /// Entry that could not be read while walking
+    Unreadable,
+}
+
+impl fmt::Display for SkipReason
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        match self
+        {
+            SkipReason::DefaultIgnore(rule) => write!(f, "default ignore '{}'", rule),
+            SkipReason::AutoExclude { name, marker } => write!(f, "auto-excluded '{}' because {} is present", name, marker),
+            SkipReason::GitIgnore(pattern) => write!(f, "gitignored by '{}'", pattern),
+            SkipReason::Exclude(pattern) => write!(f, "excluded by '{}'", pattern),
+            SkipReason::NotIncluded => write!(f, "not included"),
+            SkipReason::UnsupportedExtension => write!(f, "unsupported extension"),
+            SkipReason::Category(category) => write!(f, "{} (--detect-{} exclude)", category.name(), category.name()),
+            SkipReason::Unreadable => write!(f, "unreadable"),
+        }
+    }
+}
+
+/// A file that passed every filter and will be handed to the FileParser
+pub struct Candidate
+{
+    /// Raw DirEntry type
+    pub entry: DirEntry,
+    /// Language detected from the file extension
+    pub language: &'static str,
+    /// Size of the file on disk in bytes
+    pub size: u64,
+}
+
+/// An entry that was left out, along with the filter that removed it
+pub struct Skip
+{
+    pub path: PathBuf,
+    pub reason: SkipReason,
+    /// Size of the entry in bytes, from metadata only. Zero for directories
+    pub size: u64,
+}
+
+/// An `--exclude` or `--include` glob, matched against paths relative to the
+/// root. `*` doesn't match `/`, while `**` matches any number of directories
+#[derive(Debug, Clone)]
+pub struct PathGlob
+{
+    pub pattern: String,
+    matcher: GlobMatcher,
+}
+
+impl PathGlob
+{
+    pub fn new(pattern: &str) -> Result<PathGlob, globset::Error>
+    {
+        let matcher = GlobBuilder::new(pattern).literal_separator(true)
+                                               .build()?
+                                               .compile_matcher();
+
+        Ok(PathGlob { pattern: pattern.to_string(), matcher })
+    }
+
+    /// Directories are also tried with a trailing slash, so `third_party/**`
+    /// prunes `third_party` itself rather than each file below it
+    fn is_match(&self, relative: &str, is_dir: bool) -> bool
+    {
+        self.matcher.is_match(relative) || (is_dir && self.matcher.is_match(format!("{}/", relative)))
+    }
+}
+
+/// Filtering settings for the planner
+#[derive(Debug, Clone)]
+pub struct PlanOptions
+{
+    /// File and directory names that are never analyzed
+    pub default_ignores: Vec<String>,
+    /// Directory names skipped for the ecosystems detected at the root
+    pub auto_ignores: Vec<AutoIgnore>,
+    /// Skip what `.gitignore` files and `.git/info/exclude` ignore
+    pub gitignore: bool,
+    /// Paths below the root that are never analyzed
+    pub excludes: Vec<PathGlob>,
+    /// If any, only files matching one of these are analyzed. Excludes win
+    pub includes: Vec<PathGlob>,
+    /// If any, only files with one of these extensions, without the dot, are
+    /// analyzed
+    pub extensions: Vec<String>,
+    /// Generated and vendored files are skipped if their category is excluded
+    pub classifier: Classifier,
+}
+
+impl Default for PlanOptions
+{
+    fn default() -> PlanOptions
+    {
+        PlanOptions
+        {
+            default_ignores: DEFAULT_IGNORES.iter().map(|s| s.to_string()).collect(),
+            auto_ignores: Vec::new(),
+            gitignore: true,
+            excludes: Vec::new(),
+            includes: Vec::new(),
+            extensions: Vec::new(),
+            classifier: Classifier::default(),
+        }
+    }
+}
+
+/// A single decision made by the planner
+pub enum Planned
+{
+    Analyze(Candidate),
+    Skip(Skip),
+}
+
+/// Planner stage: walks the tree and applies every filter, yielding what would
+/// be analyzed and what was skipped without parsing anything. Both the real
+/// run and `--dry-run` go through this so they can never disagree. Entries are
+/// yielded as they are walked so large trees are never buffered.
+pub struct Planner
+{
+    walker: walkdir::IntoIter,
+    /// Further paths to walk once the current one is done, last first
+    pending: Vec<PathBuf>,
+    root: PathBuf,
+    /// Only with `options.gitignore`
+    gitignores: Option<GitIgnores>,
+    options: PlanOptions,
+}
+
+impl Planner
+{
+    /// Walk `paths`, which are all `root` or under it, one after another.
+    /// Filters see paths relative to `root`
+    pub fn new(root: &Path, paths: &[PathBuf], options: PlanOptions) -> Planner
+    {
+        let mut pending: Vec<PathBuf> = paths.iter().rev().cloned().collect();
+        let first = pending.pop().unwrap_or_else(|| root.to_path_buf());
+
+        Planner
+        {
+            walker: Planner::walk(&first),
+            pending,
+            root: root.to_path_buf(),
+            gitignores: options.gitignore.then(|| GitIgnores::new(root)),
+            options,
+        }
+    }
+
+    fn walk(path: &Path) -> walkdir::IntoIter
+    {
+        /* sorted so that two runs over the same tree produce identical output
+         * whatever order the filesystem lists directories in */
+        WalkDir::new(path).sort_by_file_name().into_iter()
+    }
+
+    /// Find the default ignore rule matching an entry, if any. The root itself
+    /// is never ignored
+    fn default_ignore(&self, entry: &DirEntry) -> Option<&str>
+    {
+        if entry.depth() == 0
+        {
+            return None;
+        }
+
+        let name = entry.file_name().to_str()?;
+
+        self.options.default_ignores.iter()
+                                    .find(|rule| rule.as_str() == name)
+                                    .map(String::as_str)
+    }
+
+    /// Find the auto-exclude rule matching a directory, if any
+    fn auto_ignore(&self, entry: &DirEntry) -> Option<&AutoIgnore>
+    {
+        if entry.depth() == 0 || !entry.file_type().is_dir()
+        {
+            return None;
+        }
+
+        let name = entry.file_name().to_str()?;
+
+        self.options.auto_ignores.iter().find(|ignore| ignore.name == name)
+    }
+
+    /// Find the `.gitignore` pattern ignoring an entry, if any. Like the
+    /// other filters, the paths being walked are never ignored themselves
+    fn gitignore(&mut self, entry: &DirEntry) -> Option<String>
+    {
+        if entry.depth() == 0
+        {
+            return None;
+        }
+
+        self.gitignores.as_mut()?.matched(entry.path(), entry.file_type().is_dir())
+    }
+
+    /// The path of an entry below the root, with `/` separators
+    fn relative(&self, entry: &DirEntry) -> String
+    {
+        let relative = entry.path().strip_prefix(&self.root).unwrap_or(entry.path());
+        let components: Vec<String> = relative.components()
+                                              .map(|c| c.as_os_str().to_string_lossy().into_owned())
+                                              .collect();
+
+        components.join("/")
+    }
+
+    /// Find the first `--exclude` pattern matching an entry, if any. Paths
+    /// listed with `--files-from` are matched too, only the root is never
+    /// excluded
+    fn exclude(&self, entry: &DirEntry) -> Option<&PathGlob>
+    {
+        if entry.path() == self.root || self.options.excludes.is_empty()
+        {
+            return None;
+        }
+
+        let relative = self.relative(entry);
+
+        self.options.excludes.iter().find(|exclude| exclude.is_match(&relative, entry.file_type().is_dir()))
+    }
+
+    /// Whether a file is in the `--include` set and has one of the
+    /// `--extensions`. Everything is without either
+    fn is_included(&self, entry: &DirEntry) -> bool
+    {
+        if !self.options.extensions.is_empty()
+        {
+            let name = entry.file_name().to_string_lossy();
+            let extension = name.rsplit_once('.').map(|(_, extension)| extension).unwrap_or_default();
+
+            if !self.options.extensions.iter().any(|e| e == extension)
+            {
+                return false;
+            }
+        }
+
+        if self.options.includes.is_empty()
+        {
+            return true;
+        }
+
+        let relative = self.relative(entry);
+
+        self.options.includes.iter().any(|include| include.is_match(&relative, false))
+    }
+}
+
+impl Iterator for Planner
+{
+    type Item = Planned;
+
+    fn next(&mut self) -> Option<Planned>
+    {
+        loop
+        {
+            let next = match self.walker.next()
+            {
+                Some(next) => next,
+                None => {
+                    self.walker = Planner::walk(&self.pending.pop()?);
+                    continue;
+                }
+            };
+
+            let entry = match next
+            {
+                Ok(entry) => entry,
+                Err(e) => {
+                    let path = e.path().map(Path::to_path_buf).unwrap_or_default();
+
+                    return Some(Planned::Skip(Skip { path, reason: SkipReason::Unreadable, size: 0 }));
+                }
+            };
+
+            let reason = if let Some(rule) = self.default_ignore(&entry)
+            {
+                Some(SkipReason::DefaultIgnore(rule.to_string()))
+            }
+            else if let Some(ignore) = self.auto_ignore(&entry)
+            {
+                Some(SkipReason::AutoExclude { name: ignore.name.clone(), marker: ignore.marker.clone() })
+            }
+            else if let Some(pattern) = self.gitignore(&entry)
+            {
+                Some(SkipReason::GitIgnore(pattern))
+            }
+            else
+            {
+                self.exclude(&entry).map(|exclude| SkipReason::Exclude(exclude.pattern.clone()))
+            };
+
+            if let Some(reason) = reason
+            {
+                /* don't descend into ignored directories */
+                if entry.file_type().is_dir()
+                {
+                    self.walker.skip_current_dir();
+                }
+
+                return Some(Planned::Skip(Skip { path: entry.into_path(), reason, size: 0 }));
+            }
+
+            /* directories are implied by the files inside them */
+            if entry.file_type().is_dir()
+            {
+                continue;
+            }
+
+            let size = entry.metadata().map(|m| m.len()).unwrap_or(0);
+
+            /* before the extension check so files left out on purpose aren't
+             * reported as unsupported languages */
+            if !self.is_included(&entry)
+            {
+                return Some(Planned::Skip(Skip { path: entry.into_path(), reason: SkipReason::NotIncluded, size }));
+            }
+
+            let name = entry.file_name().to_string_lossy();
+
+            if !file_parser::is_file_extension_valid(&name)
+            {
+                return Some(Planned::Skip(Skip { path: entry.into_path(), reason: SkipReason::UnsupportedExtension, size }));
+            }
+
+            /* last, as finding vendored files reads them */
+            if let Some(category) = self.options.classifier.excluded(entry.path())
+            {
+                return Some(Planned::Skip(Skip { path: entry.into_path(), reason: SkipReason::Category(category), size }));
+            }
+
+            let language = file_parser::file_language(&name);
+
+            return Some(Planned::Analyze(Candidate { entry, language, size }));
+        }
+    }
+}
+
+/// `count` followed by the singular or plural noun, e.g. `1 file`, `2 files`
+pub fn count(count: u64, singular: &str, plural: &str) -> String
+{
+    format!("{} {}", count, if count == 1 { singular } else { plural })
+}
+
+/// Tally of skipped entries by reason, printed at the end of a run
+#[derive(Default)]
+pub struct SkipInventory
+{
+    counts: std::collections::BTreeMap<SkipReason, u64>,
+}
+
+impl SkipInventory
+{
+    pub fn record(&mut self, skip: &Skip)
+    {
+        *self.counts.entry(skip.reason.clone()).or_insert(0) += 1;
+    }
+
+    pub fn total(&self) -> u64
+    {
+        self.counts.values().sum()
+    }
+}
+
+impl fmt::Display for SkipInventory
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        write!(f, "skipped {}", count(self.total(), "entry", "entries"))?;
+
+        for (i, (reason, count)) in self.counts.iter().enumerate()
+        {
+            write!(f, "{}{}: {}", if i == 0 { " (" } else { ", " }, reason, count)?;
+        }
+
+        if !self.counts.is_empty()
+        {
+            write!(f, ")")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Format a byte count for people, e.g. 2.1 MB
+fn human_size(bytes: u64) -> String
+{
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+
+    if bytes < 1024
+    {
+        return format!("{} B", bytes);
+    }
+
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+
+    while size >= 1024.0 && unit < UNITS.len() - 1
+    {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    if size < 10.0
+    {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+    else
+    {
+        format!("{:.0} {}", size, UNITS[unit])
+    }
+}
+
+/// Files with one unsupported extension, as written in the `json` report
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Unsupported
+{
+    /// With the dot, e.g. `.go`, or `(no extension)`
+    pub extension: String,
+    pub files: u64,
+    pub bytes: u64,
+}
+
+/// Tally of files skipped for having an extension that can't be parsed, by
+/// extension. Only metadata is used so this stays cheap on big trees
+#[derive(Default)]
+pub struct UnsupportedTally
+{
+    /// extension -> (file count, total bytes)
+    extensions: std::collections::HashMap<String, (u64, u64)>,
+}
+
+impl UnsupportedTally
+{
+    pub fn record(&mut self, skip: &Skip)
+    {
+        if skip.reason != SkipReason::UnsupportedExtension
+        {
+            return;
+        }
+
+        let extension = skip.path.extension()
+                                 .map(|e| format!(".{}", e.to_string_lossy()))
+                                 .unwrap_or_else(|| "(no extension)".to_string());
+
+        let tally = self.extensions.entry(extension).or_insert((0, 0));
+        tally.0 += 1;
+        tally.1 += skip.size;
+    }
+
+    pub fn is_empty(&self) -> bool
+    {
+        self.extensions.is_empty()
+    }
+
+    /// The tally, most common first with ties broken by extension so it is
+    /// the same on every run
+    pub fn breakdown(&self) -> Vec<Unsupported>
+    {
+        let mut breakdown: Vec<Unsupported> = self.extensions.iter()
+                                                             .map(|(extension, (files, bytes))| Unsupported { extension: extension.clone(), files: *files, bytes: *bytes })
+                                                             .collect();
+
+        breakdown.sort_by(|a, b| b.files.cmp(&a.files).then(a.extension.cmp(&b.extension)));
+
+        breakdown
+    }
+}
+
+impl fmt::Display for UnsupportedTally
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        let breakdown = self.breakdown();
+        let total: u64 = breakdown.iter().map(|u| u.files).sum();
+
+        write!(f, "skipped {}: ", count(total, "file", "files"))?;
+
+        for (i, unsupported) in breakdown.iter().enumerate()
+        {
+            write!(f, "{}{} ({}, {})", if i == 0 { "" } else { ", " }, unsupported.extension, unsupported.files, human_size(unsupported.bytes))?;
+        }
+
+        write!(f, " - these languages are not yet supported")
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use std::fs;
+
+    fn skip(path: &str, reason: SkipReason, size: u64) -> Skip
+    {
+        Skip { path: PathBuf::from(path), reason, size }
+    }
+
+    #[test]
+    fn unsupported_files_are_tallied_by_extension()
+    {
+        let mut tally = UnsupportedTally::default();
+
+        tally.record(&skip("r/a.go", SkipReason::UnsupportedExtension, 1000));
+        tally.record(&skip("r/b.sh", SkipReason::UnsupportedExtension, 10));
+        tally.record(&skip("r/c.go", SkipReason::UnsupportedExtension, 2 * 1024 * 1024));
+        tally.record(&skip("r/Makefile", SkipReason::UnsupportedExtension, 5));
+        tally.record(&skip("r/d.c", SkipReason::NotIncluded, 99));
+
+        assert_eq!(tally.breakdown(), [Unsupported { extension: ".go".to_string(), files: 2, bytes: 2 * 1024 * 1024 + 1000 },
+                                       Unsupported { extension: "(no extension)".to_string(), files: 1, bytes: 5 },
+                                       Unsupported { extension: ".sh".to_string(), files: 1, bytes: 10 }]);
+
+        assert_eq!(tally.to_string(), "skipped 4 files: .go (2, 2.0 MB), (no extension) (1, 5 B), .sh (1, 10 B) - these languages are not yet supported");
+    }
+
+    #[test]
+    fn counts_are_pluralized()
+    {
+        let mut tally = UnsupportedTally::default();
+        tally.record(&skip("r/a.txt", SkipReason::UnsupportedExtension, 2048));
+
+        assert_eq!(tally.to_string(), "skipped 1 file: .txt (1, 2.0 KB) - these languages are not yet supported");
+        assert_eq!(count(0, "entry", "entries"), "0 entries");
+        assert_eq!(count(1, "entry", "entries"), "1 entry");
+    }
+
+    #[test]
+    fn sizes_for_people()
+    {
+        assert_eq!(human_size(0), "0 B");
+        assert_eq!(human_size(1023), "1023 B");
+        assert_eq!(human_size(1536), "1.5 KB");
+        assert_eq!(human_size(90 * 1024), "90 KB");
+        assert_eq!(human_size(3 * 1024 * 1024 * 1024), "3.0 GB");
+    }
+
+    /// A tree of empty files, one per path, with directories made as needed
+    fn tree(paths: &[&str]) -> tempfile::TempDir
+    {
+        let dir = tempfile::tempdir().unwrap();
+
+        for path in paths
+        {
+            let path = dir.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+
+        dir
+    }
+
+    /// Each decision as `analyze <path>` or `skip <path>: <reason>`, with paths
+    /// relative to the root
+    fn plan(root: &Path, paths: &[PathBuf], options: PlanOptions) -> Vec<String>
+    {
+        let shown = |path: &Path| path.strip_prefix(root).unwrap().display().to_string();
+
+        Planner::new(root, paths, options).map(|planned| match planned {
+                                              Planned::Analyze(candidate) => format!("analyze {}", shown(candidate.entry.path())),
+                                              Planned::Skip(skip) => format!("skip {}: {}", shown(&skip.path), skip.reason),
+                                          })
+                                          .collect()
+    }
+
+    #[test]
+    fn plan_is_sorted_and_prunes_ignored_directories()
+    {
+        let dir = tree(&["src/b.c", "src/a.c", "node_modules/lib/x.js", "README.md", "build/gen.c"]);
+
+        assert_eq!(plan(dir.path(), &[dir.path().to_path_buf()], PlanOptions::default()),
+                   ["skip README.md: unsupported extension",
+                    "skip build: default ignore 'build'",
+                    "skip node_modules: default ignore 'node_modules'",
+                    "analyze src/a.c",
+                    "analyze src/b.c"]);
+    }
+
+    #[test]
+    fn excludes_prune_and_win_over_includes()
+    {
+        let dir = tree(&["src/a.c", "src/gen/b.c", "third_party/z/c.c", "main.py", "notes.txt"]);
+        let options = PlanOptions
+        {
+            excludes: vec![PathGlob::new("third_party/**").unwrap(), PathGlob::new("src/gen/*.c").unwrap()],
+            includes: vec![PathGlob::new("src/**").unwrap(), PathGlob::new("*.txt").unwrap()],
+            ..PlanOptions::default()
+        };
+
+        /* a file left out by --include isn't reported as unsupported */
+        assert_eq!(plan(dir.path(), &[dir.path().to_path_buf()], options),
+                   ["skip main.py: not included",
+                    "skip notes.txt: unsupported extension",
+                    "analyze src/a.c",
+                    "skip src/gen/b.c: excluded by 'src/gen/*.c'",
+                    "skip third_party: excluded by 'third_party/**'"]);
+    }
+
+    #[test]
+    fn single_stars_stay_in_one_directory()
+    {
+        let glob = PathGlob::new("src/*.c").unwrap();
+
+        assert!(glob.is_match("src/a.c", false));
+        assert!(!glob.is_match("src/sub/a.c", false));
+        assert!(PathGlob::new("vendor/**").unwrap().is_match("vendor", true));
+    }
+
+    #[test]
+    fn extensions_narrow_the_languages()
+    {
+        let dir = tree(&["a.c", "b.cpp", "c.py"]);
+        let options = PlanOptions { extensions: vec!["c".to_string(), "py".to_string()], ..PlanOptions::default() };
+
+        assert_eq!(plan(dir.path(), &[dir.path().to_path_buf()], options), ["analyze a.c", "skip b.cpp: not included", "analyze c.py"]);
+    }
+
+    #[test]
+    fn gitignore_is_followed_unless_turned_off()
+    {
+        let dir = tree(&["a.c", "gen/b.c", "sub/c.c", "sub/d.c"]);
+        fs::write(dir.path().join(".gitignore"), "gen/\n").unwrap();
+        fs::write(dir.path().join("sub").join(".gitignore"), "d.c\n").unwrap();
+
+        let all = [dir.path().to_path_buf()];
+
+        assert_eq!(plan(dir.path(), &all, PlanOptions::default()),
+                   ["skip .gitignore: unsupported extension",
+                    "analyze a.c",
+                    "skip gen: gitignored by 'gen/'",
+                    "skip sub/.gitignore: unsupported extension",
+                    "analyze sub/c.c",
+                    "skip sub/d.c: gitignored by 'd.c'"]);
+
+        let options = PlanOptions { gitignore: false, ..PlanOptions::default() };
+
+        assert!(plan(dir.path(), &all, options).contains(&"analyze gen/b.c".to_string()));
+    }
+
+    #[test]
+    fn several_paths_are_walked_in_order()
+    {
+        let dir = tree(&["a/x.c", "b/y.c", "c/z.c"]);
+        let paths = [dir.path().join("c"), dir.path().join("a")];
+
+        assert_eq!(plan(dir.path(), &paths, PlanOptions::default()), ["analyze c/z.c", "analyze a/x.c"]);
+    }
+
+    #[test]
+    fn skips_are_tallied_by_reason()
+    {
+        let mut inventory = SkipInventory::default();
+
+        assert_eq!(inventory.to_string(), "skipped 0 entries");
+
+        inventory.record(&skip("r/a.txt", SkipReason::UnsupportedExtension, 1));
+        inventory.record(&skip("r/b.txt", SkipReason::UnsupportedExtension, 1));
+        inventory.record(&skip("r/.git", SkipReason::DefaultIgnore(".git".to_string()), 0));
+
+        assert_eq!(inventory.total(), 3);
+        assert_eq!(inventory.to_string(), "skipped 3 entries (default ignore '.git': 1, unsupported extension: 2)");
+    }
+}
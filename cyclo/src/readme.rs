@@ -0,0 +1,179 @@
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::Path;
+
+use crate::escape;
+
+
+/// READMEs larger than this are only read up to this many bytes
+const MAX_README_BYTES: u64 = 64 * 1024;
+
+/// Hover text gets a line break after roughly this many characters
+const WRAP_WIDTH: usize = 60;
+
+/// Find a README* file directly inside `dir` and return the first paragraph of
+/// it, HTML-escaped and with basic markdown rendered into the small set of tags
+/// plotly hover text understands. Missing or binary READMEs return None
+pub fn excerpt(dir: &Path) -> Option<String>
+{
+    let mut names: Vec<_> = fs::read_dir(dir).ok()?
+                                             .filter_map(Result::ok)
+                                             .filter(|e| e.file_type().map(|t| t.is_file()).unwrap_or(false))
+                                             .map(|e| e.file_name())
+                                             .filter(|n| n.to_string_lossy().to_ascii_uppercase().starts_with("README"))
+                                             .collect();
+
+    /* README.md before README.txt etc, just so it is deterministic */
+    names.sort();
+
+    let mut buf = Vec::new();
+    File::open(dir.join(names.first()?)).ok()?
+                                        .take(MAX_README_BYTES)
+                                        .read_to_end(&mut buf).ok()?;
+
+    if buf.contains(&0)
+    {
+        return None;
+    }
+
+    let text = String::from_utf8(buf).ok()?;
+
+    let paragraph = first_paragraph(&text)?;
+
+    Some(wrap(&render_inline(&escape::html(&paragraph))))
+}
+
+/// The first block of text that isn't a heading, rule, badge line, or code
+/// fence, joined into one line
+fn first_paragraph(text: &str) -> Option<String>
+{
+    let mut lines = Vec::new();
+    let mut in_fence = false;
+
+    for line in text.lines()
+    {
+        let line = line.trim();
+
+        if line.starts_with("```")
+        {
+            in_fence = !in_fence;
+            continue;
+        }
+
+        let skip = in_fence
+                   || line.starts_with('#')
+                   || line.starts_with("![")
+                   || line.starts_with('<')
+                   || (!line.is_empty() && line.chars().all(|c| matches!(c, '=' | '-' | '*' | '_')));
+
+        if line.is_empty() || skip
+        {
+            if !lines.is_empty()
+            {
+                break;
+            }
+
+            continue;
+        }
+
+        lines.push(line);
+    }
+
+    if lines.is_empty()
+    {
+        None
+    }
+    else
+    {
+        Some(lines.join(" "))
+    }
+}
+
+/// Minimal inline markdown: **bold**, *italic*, `code`, and [text](url) links
+/// which are reduced to their text
+fn render_inline(s: &str) -> String
+{
+    let mut out = String::new();
+    let mut rest = s;
+
+    while let Some(c) = rest.chars().next()
+    {
+        if let Some(inner) = delimited(rest, "**")
+        {
+            out.push_str(&format!("<b>{}</b>", inner));
+            rest = &rest[inner.len() + 4..];
+        }
+        else if let Some(inner) = delimited(rest, "*")
+        {
+            out.push_str(&format!("<i>{}</i>", inner));
+            rest = &rest[inner.len() + 2..];
+        }
+        else if let Some(inner) = delimited(rest, "`")
+        {
+            out.push_str(inner);
+            rest = &rest[inner.len() + 2..];
+        }
+        else if let Some((text, len)) = link(rest)
+        {
+            out.push_str(text);
+            rest = &rest[len..];
+        }
+        else
+        {
+            out.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+
+    out
+}
+
+/// If `s` starts with `delim`, return the text up to the next `delim`
+fn delimited<'a>(s: &'a str, delim: &str) -> Option<&'a str>
+{
+    let body = s.strip_prefix(delim)?;
+    let end = body.find(delim)?;
+
+    if end == 0
+    {
+        return None;
+    }
+
+    Some(&body[..end])
+}
+
+/// If `s` starts with `[text](url)`, return the text and the length of the link
+fn link(s: &str) -> Option<(&str, usize)>
+{
+    let body = s.strip_prefix('[')?;
+    let text_end = body.find("](")?;
+    let url_end = body[text_end..].find(')')?;
+
+    Some((&body[..text_end], 1 + text_end + url_end + 1))
+}
+
+/// Insert <br> between words so hover boxes don't get too wide
+fn wrap(s: &str) -> String
+{
+    let mut out = String::new();
+    let mut width = 0;
+
+    for word in s.split(' ')
+    {
+        if width > 0 && width + word.len() > WRAP_WIDTH
+        {
+            out.push_str("<br>");
+            width = 0;
+        }
+        else if width > 0
+        {
+            out.push(' ');
+            width += 1;
+        }
+
+        out.push_str(word);
+        width += word.len();
+    }
+
+    out
+}
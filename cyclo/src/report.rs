@@ -0,0 +1,352 @@
+use clap::ValueEnum;
+use serde::{Serialize, Serializer};
+
+use crate::escape::strip_control;
+use crate::file_parser::MacroFunction;
+use crate::planner::Unsupported;
+
+/// Version of the debug file layout, written in its first line. Bumped on any
+/// change so scripts reading the file can tell
+pub const DEBUG_FORMAT_VERSION: u32 = 1;
+
+/// What the report is written as
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format
+{
+    /// Chart data for the webserver's plotly treemap
+    Js,
+    /// An object with the run's metadata and one record per file and
+    /// directory
+    Json,
+    /// One row per file and directory, for spreadsheets
+    Csv,
+    /// A SARIF 2.1.0 log with a result for each file above `--threshold`
+    Sarif,
+    /// A summary with the most complex files and a rollup per directory
+    Markdown,
+    /// CCCC-style XML with each file as a module
+    Cccc,
+    /// A Code Climate issue array for each file above `--threshold`, as
+    /// GitLab's code quality widget reads
+    Codeclimate,
+    /// A Graphviz digraph of the hierarchy, see `--dot-depth`
+    Dot,
+}
+
+/// Write `is_dir` as the record's `kind`
+fn kind<S: Serializer>(is_dir: &bool, serializer: S) -> Result<S::Ok, S::Error>
+{
+    serializer.serialize_str(if *is_dir { "dir" } else { "file" })
+}
+
+/// One box of the treemap: an analyzed file, or a directory above files.
+/// Serialized in this field order as an object of the `json` report
+#[derive(Debug, Clone, Serialize)]
+pub struct Record
+{
+    #[serde(rename = "kind", serialize_with = "kind")]
+    pub is_dir: bool,
+    pub label: String,
+    /// Label of the containing directory, empty for the top node
+    pub parent: String,
+    /// Lines of code, zero for directories
+    pub nloc: u64,
+    /// Mean function cyclomatic complexity, zero for directories
+    pub cc: f64,
+    /// Mean function compile-time complexity, see `--count-compile-time`
+    pub compile_time_cc: f64,
+    /// Estimated number of functions the means are over, zero for directories
+    pub function_count: u64,
+    /// Pseudo-functions counted in the file, see `--macros-as-functions`
+    pub functions: Vec<MacroFunction>,
+    /// Some bytes weren't valid UTF-8 and were replaced, see `--strict-utf8`
+    pub had_invalid_utf8: bool,
+    /// HTML shown under the label in the treemap's hover text, e.g. a source
+    /// link or a README excerpt. Not part of the other reports
+    #[serde(skip)]
+    pub hovertext: String,
+}
+
+impl Record
+{
+    /// `file` or `dir`, as written in the reports
+    pub fn kind(&self) -> &'static str
+    {
+        if self.is_dir { "dir" } else { "file" }
+    }
+
+    /// The label without the top node, i.e. the path below the analyzed root
+    pub fn relative_path(&self) -> &str
+    {
+        self.label.split_once('/').map(|(_, rest)| rest).unwrap_or(&self.label)
+    }
+}
+
+/// Totals over a set of files. The total cc is the mean cc times the number of
+/// functions, i.e. roughly the decisions counted, so means can be recombined
+#[derive(Default)]
+pub struct Rollup
+{
+    pub files: u64,
+    pub nloc: u64,
+    pub cc: f64,
+    pub functions: u64,
+    /// Highest mean cc of any one file
+    pub worst: f64,
+}
+
+impl Rollup
+{
+    pub fn add(&mut self, record: &Record)
+    {
+        self.files += 1;
+        self.nloc += record.nloc;
+        self.cc += record.cc * record.function_count as f64;
+        self.functions += record.function_count;
+        self.worst = self.worst.max(record.cc);
+    }
+
+    pub fn mean_cc(&self) -> f64
+    {
+        if self.functions == 0 { 0.0 } else { self.cc / self.functions as f64 }
+    }
+}
+
+/// The directory a file is rolled up into: its first `depth` directories below
+/// the root, or fewer if it isn't that deep. Files directly under the root are
+/// rolled up as `.`
+pub fn directory(relative_path: &str, depth: usize) -> &str
+{
+    let end = relative_path.match_indices('/')
+                           .map(|(i, _)| i)
+                           .take(depth)
+                           .last();
+
+    match end
+    {
+        Some(end) => &relative_path[..end],
+        None => ".",
+    }
+}
+
+/// Files whose mean cc is above `threshold`, worst first with ties broken by
+/// label so the order is the same on every run. At most `max` are returned,
+/// along with the number left out
+pub fn findings(records: &[Record], threshold: f64, max: Option<usize>) -> (Vec<&Record>, usize)
+{
+    let mut over: Vec<&Record> = records.iter()
+                                        .filter(|r| !r.is_dir && r.cc > threshold)
+                                        .collect();
+
+    over.sort_by(|a, b| b.cc.total_cmp(&a.cc).then(a.label.cmp(&b.label)));
+
+    let suppressed = match max
+    {
+        Some(max) if over.len() > max => over.len() - max,
+        _ => 0,
+    };
+
+    over.truncate(over.len() - suppressed);
+
+    (over, suppressed)
+}
+
+/// What the `json` report says about the run as a whole
+#[derive(Debug, Default, Serialize)]
+pub struct Metadata
+{
+    /// Files left out for their extension, see `W_UNSUPPORTED`
+    pub unsupported: Vec<Unsupported>,
+}
+
+/// Render the metadata and the records as a JSON object, with a record per
+/// line so the output diffs well
+pub fn json(metadata: &Metadata, records: &[Record]) -> String
+{
+    /* structs of strings, numbers, and bools always serialize */
+    let objects: Vec<String> = records.iter()
+                                      .map(|r| format!("    {}", serde_json::to_string(r).unwrap()))
+                                      .collect();
+
+    format!("{{\n  \"metadata\": {},\n  \"records\": [\n{}\n  ]\n}}\n", serde_json::to_string(metadata).unwrap(), objects.join(",\n"))
+}
+
+/// Render the records as the debug file: a version line, a header, and a
+/// tab-separated line per record followed by one per pseudo-function. The
+/// layout is documented in the README and must only change along with
+/// `DEBUG_FORMAT_VERSION`
+pub fn debug(records: &[Record]) -> String
+{
+    let mut out = format!("# cyclo debug v{}\nkind\tlabel\tnloc\tcc\tcompile_time_cc\n", DEBUG_FORMAT_VERSION);
+
+    for record in records
+    {
+        out.push_str(&format!("{}\t{}\t{}\t{:.4}\t{:.4}\n", record.kind(), strip_control(&record.label), record.nloc, record.cc, record.compile_time_cc));
+
+        for m in &record.functions
+        {
+            out.push_str(&format!("\tfunction\t{}\t-\t{:.4}\t-\n", strip_control(&m.name), m.cc as f64));
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn file(label: &str, nloc: u64, cc: f64, function_count: u64) -> Record
+    {
+        Record
+        {
+            is_dir: false,
+            label: label.to_string(),
+            parent: label.rsplit_once('/').map(|(parent, _)| parent.to_string()).unwrap_or_default(),
+            nloc,
+            cc,
+            compile_time_cc: 0.0,
+            function_count,
+            functions: Vec::new(),
+            had_invalid_utf8: false,
+            hovertext: String::new(),
+        }
+    }
+
+    #[test]
+    fn json_has_one_record_per_line()
+    {
+        let mut mac = file("test/mac.c", 10, 5.0, 3);
+        mac.functions.push(MacroFunction { name: "MACRO:MAX".to_string(), cc: 2 });
+
+        let mut dir = file("test", 0, 0.0, 0);
+        dir.is_dir = true;
+
+        let mut odd = file("test/a\"b\n.c", 1, 1.0 / 3.0, 1);
+        odd.had_invalid_utf8 = true;
+
+        let metadata = Metadata
+        {
+            unsupported: vec![Unsupported { extension: ".go".to_string(), files: 2, bytes: 300 }],
+        };
+
+        assert_eq!(json(&metadata, &[mac, dir, odd]), concat!(
+            "{\n",
+            r#"  "metadata": {"unsupported":[{"extension":".go","files":2,"bytes":300}]},"#, "\n",
+            r#"  "records": ["#, "\n",
+            r#"    {"kind":"file","label":"test/mac.c","parent":"test","nloc":10,"cc":5.0,"compile_time_cc":0.0,"function_count":3,"functions":[{"name":"MACRO:MAX","cc":2}],"had_invalid_utf8":false},"#, "\n",
+            r#"    {"kind":"dir","label":"test","parent":"","nloc":0,"cc":0.0,"compile_time_cc":0.0,"function_count":0,"functions":[],"had_invalid_utf8":false},"#, "\n",
+            r#"    {"kind":"file","label":"test/a\"b\n.c","parent":"test","nloc":1,"cc":0.3333333333333333,"compile_time_cc":0.0,"function_count":1,"functions":[],"had_invalid_utf8":true}"#, "\n",
+            "  ]\n",
+            "}\n"));
+    }
+
+    #[test]
+    fn json_parses_back()
+    {
+        let value: serde_json::Value = serde_json::from_str(&json(&Metadata::default(), &[file("test/main.c", 46, 2.5, 2)])).unwrap();
+
+        assert_eq!(value["records"][0]["label"], "test/main.c");
+        assert_eq!(value["records"][0]["cc"], 2.5);
+        assert_eq!(value["metadata"]["unsupported"], serde_json::json!([]));
+
+        let empty: serde_json::Value = serde_json::from_str(&json(&Metadata::default(), &[])).unwrap();
+
+        assert_eq!(empty["records"], serde_json::json!([]));
+    }
+
+    #[test]
+    fn debug_file_layout_is_stable()
+    {
+        let mut mac = file("test/mac.c", 10, 5.0, 3);
+        mac.compile_time_cc = 0.5;
+        mac.functions.push(MacroFunction { name: "MACRO:MAX".to_string(), cc: 2 });
+        mac.functions.push(MacroFunction { name: "MACRO:MIN".to_string(), cc: 0 });
+
+        let mut dir = file("test", 0, 0.0, 0);
+        dir.is_dir = true;
+
+        let odd = file("test/a\tb\n.c", 1, 1.0 / 3.0, 1);
+
+        assert_eq!(debug(&[mac, dir, odd]), concat!(
+            "# cyclo debug v1\n",
+            "kind\tlabel\tnloc\tcc\tcompile_time_cc\n",
+            "file\ttest/mac.c\t10\t5.0000\t0.5000\n",
+            "\tfunction\tMACRO:MAX\t-\t2.0000\t-\n",
+            "\tfunction\tMACRO:MIN\t-\t0.0000\t-\n",
+            "dir\ttest\t0\t0.0000\t0.0000\n",
+            "file\ttest/a?b?.c\t1\t0.3333\t0.0000\n"));
+    }
+
+    #[test]
+    fn debug_lines_have_a_field_per_column()
+    {
+        let mut mac = file("test/mac.c", 10, 5.0, 3);
+        mac.functions.push(MacroFunction { name: "MACRO:\tODD".to_string(), cc: 2 });
+
+        for line in debug(&[mac, file("test/x\ty.c", 1, 1.0, 1)]).lines().skip(1)
+        {
+            /* function records are indented by a tab */
+            let fields = line.strip_prefix('\t').unwrap_or(line).split('\t').count();
+            assert_eq!(fields, 5, "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn findings_are_worst_first_with_ties_by_label()
+    {
+        let mut dir = file("t", 0, 99.0, 0);
+        dir.is_dir = true;
+
+        let records = [file("t/b.c", 1, 4.0, 1), file("t/low.c", 1, 2.0, 1), dir, file("t/a.c", 1, 4.0, 1), file("t/worst.c", 1, 9.0, 1)];
+        let (findings, suppressed) = findings(&records, 2.0, None);
+        let labels: Vec<&str> = findings.iter().map(|r| r.label.as_str()).collect();
+
+        /* directories aren't findings, and a file at the threshold isn't over it */
+        assert_eq!(labels, ["t/worst.c", "t/a.c", "t/b.c"]);
+        assert_eq!(suppressed, 0);
+    }
+
+    #[test]
+    fn findings_are_capped()
+    {
+        let records: Vec<Record> = (0..10).map(|i| file(&format!("t/{}.c", i), 1, i as f64, 1)).collect();
+
+        let (kept, suppressed) = findings(&records, 0.0, Some(3));
+        let labels: Vec<&str> = kept.iter().map(|r| r.label.as_str()).collect();
+
+        assert_eq!(labels, ["t/9.c", "t/8.c", "t/7.c"]);
+        assert_eq!(suppressed, 6);
+
+        assert_eq!(findings(&records, 0.0, Some(9)).1, 0);
+        let (none, suppressed) = findings(&records, 0.0, Some(0));
+
+        assert!(none.is_empty());
+        assert_eq!(suppressed, 9);
+    }
+
+    #[test]
+    fn rollup_recombines_means()
+    {
+        let mut rollup = Rollup::default();
+        rollup.add(&file("t/a.c", 10, 2.0, 2));
+        rollup.add(&file("t/b.c", 5, 5.0, 1));
+
+        assert_eq!(rollup.files, 2);
+        assert_eq!(rollup.nloc, 15);
+        assert_eq!(rollup.cc, 9.0);
+        assert_eq!(rollup.mean_cc(), 3.0);
+        assert_eq!(rollup.worst, 5.0);
+        assert_eq!(Rollup::default().mean_cc(), 0.0);
+    }
+
+    #[test]
+    fn directories_by_depth()
+    {
+        assert_eq!(directory("net/tcp/conn.c", 1), "net");
+        assert_eq!(directory("net/tcp/conn.c", 2), "net/tcp");
+        assert_eq!(directory("net/tcp/conn.c", 5), "net/tcp");
+        assert_eq!(directory("main.c", 1), ".");
+    }
+}
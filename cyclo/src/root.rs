@@ -0,0 +1,263 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use snafu::prelude::*;
+
+
+/// Errors returned when the path to analyze can't be used
+#[derive(Debug, Snafu)]
+pub enum RootError
+{
+    /// `~` was used but there is no home directory to expand it to
+    #[snafu(display("'{path}' starts with '~' but the home directory is unknown"))]
+    NoHome { path: String },
+    /// Nothing exists at the path
+    #[snafu(display("'{path}' does not exist"))]
+    NotFound { path: String },
+    /// The path exists but can't be resolved or listed, usually permissions
+    #[snafu(display("'{path}' could not be read: {source}"))]
+    Unreadable { path: String, source: std::io::Error },
+}
+
+/// The home directory, from `HOME` or `USERPROFILE` on Windows
+fn home_dir() -> Option<PathBuf>
+{
+    std::env::var_os("HOME").or_else(|| std::env::var_os("USERPROFILE"))
+                            .filter(|home| !home.is_empty())
+                            .map(PathBuf::from)
+}
+
+/// Expand a leading `~` or `~/` to `home`. `~user` is left alone, as the
+/// shell would have expanded it if it could
+fn expand_tilde(path: &Path, home: Option<PathBuf>) -> Result<PathBuf, RootError>
+{
+    let text = path.to_string_lossy();
+
+    let rest = match text.strip_prefix('~')
+    {
+        Some(rest) if rest.is_empty() || rest.starts_with(['/', '\\']) => rest.trim_start_matches(['/', '\\']),
+        _ => return Ok(path.to_path_buf()),
+    };
+
+    let home = home.context(NoHomeSnafu { path: text.as_ref() })?;
+
+    Ok(if rest.is_empty() { home } else { home.join(rest) })
+}
+
+/// Turn the `--path` argument into the root used by both the walker and the
+/// labeler. The result is absolute, has no trailing separators or `.`/`..`
+/// components, and is known to be readable, so anything that goes wrong later
+/// is about a single file rather than the whole run
+pub fn normalize(path: &Path) -> Result<PathBuf, RootError>
+{
+    let shown = path.to_string_lossy();
+
+    let expanded = expand_tilde(path, home_dir())?;
+
+    if !expanded.exists()
+    {
+        return NotFoundSnafu { path: shown.as_ref() }.fail();
+    }
+
+    /* resolves trailing separators, `..`, and symlinks. UNC shares and drive
+     * paths on Windows come back in their verbatim form, which is still a
+     * prefix of everything walked under them */
+    let root = expanded.canonicalize().context(UnreadableSnafu { path: shown.as_ref() })?;
+
+    /* check up front that the root can actually be listed, rather than failing
+     * on the first entry of the walk */
+    if root.is_dir()
+    {
+        fs::read_dir(&root).context(UnreadableSnafu { path: shown.as_ref() })?;
+    }
+    else
+    {
+        fs::File::open(&root).context(UnreadableSnafu { path: shown.as_ref() })?;
+    }
+
+    Ok(root)
+}
+
+/// The deepest directory that every path is in, which is walked from and
+/// which relative paths, globs, and git are taken against when analyzing
+/// several paths. Paths inside another of the paths are dropped, and the rest
+/// are sorted so the walk order doesn't depend on the order they were given in
+pub fn common_ancestor(paths: &[PathBuf]) -> (PathBuf, Vec<PathBuf>)
+{
+    let mut paths = paths.to_vec();
+    paths.sort();
+    paths.dedup();
+
+    let outer: Vec<PathBuf> = paths.iter()
+                                   .filter(|path| !paths.iter().any(|other| other != *path && path.starts_with(other)))
+                                   .cloned()
+                                   .collect();
+
+    let mut ancestor = outer.first().cloned().unwrap_or_default();
+
+    /* a single path is its own root, even a file */
+    if outer.len() > 1
+    {
+        while !outer.iter().all(|path| path.starts_with(&ancestor))
+        {
+            if !ancestor.pop()
+            {
+                break;
+            }
+        }
+    }
+
+    (ancestor, outer)
+}
+
+/// A path as it should be shown to people. Canonical paths on Windows carry
+/// the `\\?\` extended-length prefix, which is kept for opening files deep in
+/// the tree but is noise in messages
+pub fn display(path: &Path) -> String
+{
+    let text = path.to_string_lossy();
+
+    if let Some(share) = text.strip_prefix(r"\\?\UNC\")
+    {
+        format!(r"\\{}", share)
+    }
+    else if let Some(local) = text.strip_prefix(r"\\?\")
+    {
+        local.to_string()
+    }
+    else
+    {
+        text.into_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn common_ancestor_of_several_paths()
+    {
+        let (root, paths) = common_ancestor(&[PathBuf::from("/r/host"), PathBuf::from("/r/firmware"), PathBuf::from("/r/a/common")]);
+
+        assert_eq!(root, PathBuf::from("/r"));
+        assert_eq!(paths, [PathBuf::from("/r/a/common"), PathBuf::from("/r/firmware"), PathBuf::from("/r/host")]);
+    }
+
+    #[test]
+    fn nested_and_repeated_paths_are_dropped()
+    {
+        let (root, paths) = common_ancestor(&[PathBuf::from("/r/src/net"), PathBuf::from("/r/src"), PathBuf::from("/r/src")]);
+
+        assert_eq!(root, PathBuf::from("/r/src"));
+        assert_eq!(paths, [PathBuf::from("/r/src")]);
+    }
+
+    #[test]
+    fn single_file_is_its_own_root()
+    {
+        let (root, paths) = common_ancestor(&[PathBuf::from("/r/src/main.c")]);
+
+        assert_eq!(root, PathBuf::from("/r/src/main.c"));
+        assert_eq!(paths, [PathBuf::from("/r/src/main.c")]);
+    }
+
+    #[test]
+    fn tilde_is_the_home_directory()
+    {
+        let home = || Some(PathBuf::from("/home/me"));
+
+        assert_eq!(expand_tilde(Path::new("~"), home()).unwrap(), PathBuf::from("/home/me"));
+        assert_eq!(expand_tilde(Path::new("~/src"), home()).unwrap(), PathBuf::from("/home/me/src"));
+        assert_eq!(expand_tilde(Path::new("~//src/"), home()).unwrap(), PathBuf::from("/home/me/src/"));
+        assert_eq!(expand_tilde(Path::new(r"~\src"), home()).unwrap(), PathBuf::from("/home/me/src"));
+    }
+
+    #[test]
+    fn other_tildes_are_left_alone()
+    {
+        assert_eq!(expand_tilde(Path::new("~other/src"), None).unwrap(), PathBuf::from("~other/src"));
+        assert_eq!(expand_tilde(Path::new("src/~"), None).unwrap(), PathBuf::from("src/~"));
+        assert!(matches!(expand_tilde(Path::new("~/src"), None), Err(RootError::NoHome { .. })));
+    }
+
+    #[test]
+    fn roots_are_absolute_and_clean()
+    {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("src");
+        fs::create_dir(&src).unwrap();
+
+        let root = src.canonicalize().unwrap();
+
+        assert_eq!(normalize(&src).unwrap(), root);
+        assert_eq!(normalize(&PathBuf::from(format!("{}/", src.display()))).unwrap(), root);
+        assert_eq!(normalize(&PathBuf::from(format!("{}//", src.display()))).unwrap(), root);
+        assert_eq!(normalize(&src.join(".")).unwrap(), root);
+        assert_eq!(normalize(&src.join("..").join("src")).unwrap(), root);
+        assert!(normalize(&src).unwrap().is_absolute());
+    }
+
+    #[test]
+    fn a_file_can_be_the_root()
+    {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("main.c");
+        fs::write(&file, "").unwrap();
+
+        assert_eq!(normalize(&file).unwrap(), file.canonicalize().unwrap());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn symlinked_roots_are_resolved()
+    {
+        let dir = tempfile::tempdir().unwrap();
+        let real = dir.path().join("real");
+        fs::create_dir(&real).unwrap();
+        std::os::unix::fs::symlink(&real, dir.path().join("link")).unwrap();
+
+        assert_eq!(normalize(&dir.path().join("link")).unwrap(), real.canonicalize().unwrap());
+    }
+
+    #[test]
+    fn missing_roots_are_reported_as_written()
+    {
+        let dir = tempfile::tempdir().unwrap();
+        let missing = dir.path().join("nope");
+
+        match normalize(&missing)
+        {
+            Err(RootError::NotFound { path }) => assert_eq!(path, missing.to_string_lossy()),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn unlistable_roots_are_refused_up_front()
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let locked = dir.path().join("locked");
+        fs::create_dir(&locked).unwrap();
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o000)).unwrap();
+
+        /* root can list anything, so there is nothing to check then */
+        if fs::read_dir(&locked).is_err()
+        {
+            assert!(matches!(normalize(&locked), Err(RootError::Unreadable { .. })));
+        }
+
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    #[test]
+    fn windows_prefixes_are_hidden()
+    {
+        assert_eq!(display(Path::new(r"\\?\C:\src\main.c")), r"C:\src\main.c");
+        assert_eq!(display(Path::new(r"\\?\UNC\server\share\src")), r"\\server\share\src");
+        assert_eq!(display(Path::new("/usr/src")), "/usr/src");
+    }
+}
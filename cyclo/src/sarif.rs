@@ -0,0 +1,137 @@
+use std::path::Path;
+
+use crate::escape::{json_string, url_segment};
+use crate::report::{self, Record};
+use crate::planner;
+use crate::root;
+
+
+/// The one rule every result is reported under
+const RULE_ID: &str = "cyclomatic-complexity";
+
+/// `file://` URI of the analyzed root, with a trailing slash so relative
+/// result URIs resolve below it
+pub fn root_uri(root: &Path) -> String
+{
+    let path = root::display(root).replace('\\', "/");
+
+    let segments: Vec<String> = path.split('/')
+                                    .filter(|s| !s.is_empty())
+                                    .map(url_segment)
+                                    .collect();
+
+    if segments.is_empty()
+    {
+        return "file:///".to_string();
+    }
+
+    format!("file:///{}/", segments.join("/"))
+}
+
+/// Render a SARIF 2.1.0 log with a result for each file whose mean cc is above
+/// `threshold`, worst first. Results point at the first line, as complexity is
+/// only known per file. Past `max_findings` a single note says how many more
+/// there were. `root_uri` is left out when the root itself shouldn't be shown
+pub fn render(records: &[Record], root_uri: Option<&str>, threshold: f64, max_findings: Option<usize>) -> String
+{
+    let (findings, suppressed) = report::findings(records, threshold, max_findings);
+
+    let mut results: Vec<String> = findings.iter()
+                                          .map(|r| {
+                                              let uri: Vec<String> = r.relative_path().split('/').map(url_segment).collect();
+
+                                              format!(r#"        {{"ruleId":"{}","level":"warning","message":{{"text":{}}},"locations":[{{"physicalLocation":{{"artifactLocation":{{"uri":{},"uriBaseId":"SRCROOT"}},"region":{{"startLine":1}}}}}}],"properties":{{"cc":{:.4},"nloc":{}}}}}"#,
+                                                      RULE_ID,
+                                                      json_string(&format!("mean function cyclomatic complexity {:.2} is above {}", r.cc, threshold)),
+                                                      json_string(&uri.join("/")),
+                                                      r.cc,
+                                                      r.nloc)
+                                          })
+                                          .collect();
+
+    if suppressed > 0
+    {
+        results.push(format!(r#"        {{"ruleId":"{}","level":"note","message":{{"text":{}}}}}"#,
+                             RULE_ID,
+                             json_string(&format!("{} suppressed by --max-findings", planner::count(suppressed as u64, "more finding", "more findings")))));
+    }
+
+    let base = match root_uri
+    {
+        Some(uri) => format!(r#"{{"SRCROOT":{{"uri":{}}}}}"#, json_string(uri)),
+        None => "{}".to_string(),
+    };
+
+    format!(r#"{{
+  "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+  "version": "2.1.0",
+  "runs": [
+    {{
+      "tool": {{"driver": {{"name": "cyclo", "version": {}, "rules": [{{"id": "{}", "shortDescription": {{"text": "Mean function cyclomatic complexity above the threshold"}}}}]}}}},
+      "originalUriBaseIds": {},
+      "results": [
+{}
+      ]
+    }}
+  ]
+}}
+"#,
+            json_string(env!("CARGO_PKG_VERSION")),
+            RULE_ID,
+            base,
+            results.join(",\n"))
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn file(label: &str, cc: f64) -> Record
+    {
+        Record
+        {
+            is_dir: false,
+            label: label.to_string(),
+            parent: "test".to_string(),
+            nloc: 10,
+            cc,
+            compile_time_cc: 0.0,
+            function_count: 1,
+            functions: Vec::new(),
+            had_invalid_utf8: false,
+            hovertext: String::new(),
+        }
+    }
+
+    #[test]
+    fn results_are_worst_first_with_a_note_for_the_rest()
+    {
+        let records = [file("test/a.c", 12.0), file("test/sub/b c.c", 30.0), file("test/d.c", 5.0), file("test/e.c", 20.0)];
+        let log: serde_json::Value = serde_json::from_str(&render(&records, Some("file:///src/"), 10.0, Some(2))).unwrap();
+        let results = log["runs"][0]["results"].as_array().unwrap();
+
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0]["locations"][0]["physicalLocation"]["artifactLocation"]["uri"], "sub/b%20c.c");
+        assert_eq!(results[1]["locations"][0]["physicalLocation"]["artifactLocation"]["uri"], "e.c");
+        assert_eq!(results[2]["level"], "note");
+        assert_eq!(results[2]["message"]["text"], "1 more finding suppressed by --max-findings");
+        assert_eq!(log["runs"][0]["originalUriBaseIds"]["SRCROOT"]["uri"], "file:///src/");
+    }
+
+    #[test]
+    fn an_empty_log_is_valid()
+    {
+        let log: serde_json::Value = serde_json::from_str(&render(&[file("test/a.c", 1.0)], None, 10.0, None)).unwrap();
+
+        assert_eq!(log["runs"][0]["results"], serde_json::json!([]));
+        assert_eq!(log["runs"][0]["originalUriBaseIds"], serde_json::json!({}));
+    }
+
+    #[test]
+    fn root_uris_are_encoded_and_end_in_a_slash()
+    {
+        assert_eq!(root_uri(Path::new("/home/me/my project")), "file:///home/me/my%20project/");
+        assert_eq!(root_uri(Path::new("/")), "file:///");
+    }
+}
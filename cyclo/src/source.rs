@@ -0,0 +1,31 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+
+/// A source file decoded as UTF-8. Everything that looks at the text of a file
+/// goes through this, so slicing it always lands on a char boundary
+pub struct Source
+{
+    pub text: String,
+    /// Some bytes weren't valid UTF-8 and were replaced with U+FFFD
+    pub had_invalid_utf8: bool,
+}
+
+/// Read and decode a whole file. Invalid sequences are replaced rather than
+/// failing the file, as they are almost always in comments or string literals
+/// where they don't change the counts
+pub fn read(path: &Path) -> io::Result<Source>
+{
+    let bytes = fs::read(path)?;
+
+    Ok(match String::from_utf8(bytes)
+    {
+        Ok(text) => Source { text, had_invalid_utf8: false },
+        Err(e) => Source
+        {
+            text: String::from_utf8_lossy(e.as_bytes()).into_owned(),
+            had_invalid_utf8: true,
+        },
+    })
+}
@@ -0,0 +1,84 @@
+use std::path::{Path, PathBuf};
+use rusqlite::{params, Connection};
+use snafu::prelude::*;
+
+use crate::report::Record;
+
+
+/// Tables are created if missing, so a new file and one from earlier runs are
+/// handled the same way
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS runs (
+    id INTEGER PRIMARY KEY,
+    timestamp TEXT NOT NULL,
+    root TEXT NOT NULL,
+    options TEXT NOT NULL,
+    git_commit TEXT
+);
+CREATE TABLE IF NOT EXISTS files (
+    run_id INTEGER NOT NULL REFERENCES runs (id),
+    label TEXT NOT NULL,
+    parent TEXT NOT NULL,
+    nloc INTEGER NOT NULL,
+    cc REAL NOT NULL
+);
+CREATE INDEX IF NOT EXISTS files_run_label ON files (run_id, label);
+";
+
+/// Errors returned when a run couldn't be recorded
+#[derive(Debug, Snafu)]
+pub enum SqliteError
+{
+    /// The database couldn't be opened or isn't one
+    #[snafu(display("could not open {}: {source}", path.display()))]
+    Open { path: PathBuf, source: rusqlite::Error },
+    /// The run couldn't be written, e.g. because the database is locked
+    #[snafu(display("could not write to {}: {source}", path.display()))]
+    Write { path: PathBuf, source: rusqlite::Error },
+}
+
+/// What is recorded about a run besides its files
+pub struct Run<'a>
+{
+    /// When the run was made as ISO 8601, or empty with `--no-timestamps`
+    pub timestamp: &'a str,
+    /// The analyzed root as shown to people
+    pub root: &'a str,
+    /// The flags that change the numbers, so runs can be compared fairly
+    pub options: &'a str,
+    /// Commit checked out in the root, if it is in a git repository
+    pub git_commit: Option<&'a str>,
+}
+
+/// Append a run and its files to the database at `path`, creating it if
+/// needed. Directories aren't stored, since they have no metrics of their own.
+/// Everything is written in one transaction, so a failed run leaves no trace.
+/// Returns the id of the new run
+pub fn append(path: &Path, run: &Run, records: &[Record]) -> Result<i64, SqliteError>
+{
+    let mut connection = Connection::open(path).context(OpenSnafu { path })?;
+    connection.execute_batch(SCHEMA).context(OpenSnafu { path })?;
+
+    let tx = connection.transaction().context(WriteSnafu { path })?;
+
+    tx.execute("INSERT INTO runs (timestamp, root, options, git_commit) VALUES (?1, ?2, ?3, ?4)",
+               params![run.timestamp, run.root, run.options, run.git_commit])
+      .context(WriteSnafu { path })?;
+
+    let run_id = tx.last_insert_rowid();
+
+    {
+        let mut insert = tx.prepare("INSERT INTO files (run_id, label, parent, nloc, cc) VALUES (?1, ?2, ?3, ?4, ?5)")
+                           .context(WriteSnafu { path })?;
+
+        for r in records.iter().filter(|r| !r.is_dir)
+        {
+            insert.execute(params![run_id, r.label, r.parent, r.nloc as i64, r.cc])
+                  .context(WriteSnafu { path })?;
+        }
+    }
+
+    tx.commit().context(WriteSnafu { path })?;
+
+    Ok(run_id)
+}
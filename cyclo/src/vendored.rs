@@ -0,0 +1,26 @@
+use std::path::Path;
+
+use crate::source;
+
+
+/// Only the license header at the top of a file is looked at
+const HEADER_LINES: usize = 40;
+
+/// The first copyright line in the header of the file that doesn't mention
+/// any of `ours`, compared case-insensitively. Such a line names another
+/// project, so the file was most likely copied in. Files without a copyright
+/// line, or that can't be read, aren't vendored
+pub fn detect(path: &Path, ours: &[String]) -> Option<String>
+{
+    let ours: Vec<String> = ours.iter().map(|o| o.to_lowercase()).collect();
+    let source = source::read(path).ok()?;
+
+    source.text
+          .lines()
+          .take(HEADER_LINES)
+          .map(|line| line.trim().trim_start_matches(['/', '*', '#']).trim().to_string())
+          .find(|line| {
+              let lower = line.to_lowercase();
+              lower.contains("copyright") && !ours.iter().any(|o| lower.contains(o.as_str()))
+          })
+}
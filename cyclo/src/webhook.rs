@@ -0,0 +1,125 @@
+use std::time::Duration;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use snafu::prelude::*;
+
+use crate::escape::json_string;
+
+
+/// Number of files listed in the payload, most complex first
+const TOP_FILES: usize = 10;
+
+/// Errors returned when the summary couldn't be delivered. The URL is left out
+/// of the messages since it is often a secret itself
+#[derive(Debug, Snafu)]
+pub enum WebhookError
+{
+    /// The endpoint answered with an error status
+    #[snafu(display("the webhook answered with status {status}"))]
+    Status { status: u16 },
+    /// The endpoint couldn't be reached or didn't answer in time
+    #[snafu(display("could not reach the webhook: {message}"))]
+    Transport { message: String },
+}
+
+/// Where and how to send the summary
+pub struct WebhookOptions
+{
+    pub url: String,
+    pub timeout: Duration,
+    /// Key for the `X-Cyclo-Signature` HMAC header, if any
+    pub secret: Option<String>,
+}
+
+/// One analyzed file as it appears in the payload
+pub struct FileSummary
+{
+    pub label: String,
+    pub nloc: u64,
+    pub cc: f64,
+    /// Link to the file from `--link-template`, if there is one
+    pub url: Option<String>,
+}
+
+/// Render the JSON payload sent after a run: totals, and the files with the
+/// highest mean cc
+pub fn payload(root: &str, files: &[FileSummary]) -> String
+{
+    let nloc: u64 = files.iter().map(|f| f.nloc).sum();
+    let mean_cc = if files.is_empty() { 0.0 } else { files.iter().map(|f| f.cc).sum::<f64>() / files.len() as f64 };
+
+    /* highest cc first, ties broken by label so the payload is stable */
+    let mut top: Vec<&FileSummary> = files.iter().collect();
+    top.sort_by(|a, b| b.cc.total_cmp(&a.cc).then(a.label.cmp(&b.label)));
+
+    let top: Vec<String> = top.iter()
+                              .take(TOP_FILES)
+                              .map(|f| format!(r#"{{"path":{},"nloc":{},"cc":{:.2},"url":{}}}"#,
+                                               json_string(&f.label),
+                                               f.nloc,
+                                               f.cc,
+                                               f.url.as_deref().map(json_string).unwrap_or_else(|| "null".to_string())))
+                              .collect();
+
+    format!(r#"{{"tool":"cyclo","version":{},"root":{},"files":{},"nloc":{},"mean_cc":{:.2},"top":[{}]}}"#,
+            json_string(env!("CARGO_PKG_VERSION")),
+            json_string(root),
+            files.len(),
+            nloc,
+            mean_cc,
+            top.join(","))
+}
+
+/// Hex encoded HMAC-SHA256 of the payload
+fn signature(secret: &str, payload: &str) -> String
+{
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any length");
+    mac.update(payload.as_bytes());
+
+    mac.finalize()
+       .into_bytes()
+       .iter()
+       .map(|b| format!("{:02x}", b))
+       .collect()
+}
+
+/// POST the payload, trying once more if the first attempt fails with a
+/// transport error or a server error
+pub fn post(options: &WebhookOptions, payload: &str) -> Result<(), WebhookError>
+{
+    let agent = ureq::AgentBuilder::new().timeout(options.timeout)
+                                         .user_agent(concat!("cyclo/", env!("CARGO_PKG_VERSION")))
+                                         .build();
+
+    let send = ||
+    {
+        let mut request = agent.post(&options.url).set("Content-Type", "application/json");
+
+        if let Some(secret) = &options.secret
+        {
+            request = request.set("X-Cyclo-Signature", &format!("sha256={}", signature(secret, payload)));
+        }
+
+        match request.send_string(payload)
+        {
+            Ok(_) => Ok(()),
+            Err(ureq::Error::Status(status, _)) => StatusSnafu { status }.fail(),
+            Err(ureq::Error::Transport(e)) => {
+                let message = match e.message()
+                {
+                    Some(detail) => format!("{}: {}", e.kind(), detail),
+                    None => e.kind().to_string(),
+                };
+
+                TransportSnafu { message }.fail()
+            },
+        }
+    };
+
+    match send()
+    {
+        Err(WebhookError::Status { status }) if status < 500 => StatusSnafu { status }.fail(),
+        Err(_) => send(),
+        Ok(()) => Ok(()),
+    }
+}
@@ -0,0 +1,537 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use assert_cmd::Command;
+use tempfile::TempDir;
+
+
+/// The cyclo binary, with nothing from the environment changing its output
+fn cyclo() -> Command
+{
+    let mut cmd = Command::cargo_bin("cyclo").unwrap();
+    cmd.env_remove("SOURCE_DATE_EPOCH")
+       .env("NO_COLOR", "1");
+    cmd
+}
+
+/// A small project with two C files, one of them with a tab and a newline in
+/// its name, which are legal on unix
+fn fixture() -> TempDir
+{
+    let dir = tempfile::tempdir().unwrap();
+    let src = dir.path().join("src");
+
+    fs::create_dir(&src).unwrap();
+    fs::write(dir.path().join("main.c"), "int main(int argc, char **argv)\n{\n    if (argc > 1 && argv[1])\n    {\n        return 1;\n    }\n    return 0;\n}\n").unwrap();
+    fs::write(src.join("we\tird\nname.c"), "int f(int x)\n{\n    while (x)\n    {\n        x--;\n    }\n    return x;\n}\n").unwrap();
+
+    dir
+}
+
+/// Run cyclo on `dir` with `args`, returning what it wrote to `name`
+fn report(dir: &Path, name: &str, args: &[&str]) -> String
+{
+    let out: PathBuf = dir.join(name);
+
+    cyclo().arg("-p").arg(dir)
+           .args(["--root-label", "project", "-o"]).arg(&out)
+           .args(args)
+           .assert()
+           .success();
+
+    fs::read_to_string(out).unwrap()
+}
+
+#[test]
+fn control_characters_in_names_keep_every_format_well_formed()
+{
+    let dir = fixture();
+    /* sarif has the path as a URI, so it is percent-encoded */
+    for (format, name) in [("json", r"we\tird\nname.c"), ("sarif", "we%09ird%0Aname.c"), ("codeclimate", r"we\tird\nname.c")]
+    {
+        let text = report(dir.path(), &format!("report.{}", format), &["--format", format, "--threshold", "0"]);
+        let parsed: Result<serde_json::Value, _> = serde_json::from_str(&text);
+
+        assert!(parsed.is_ok(), "{} is not valid JSON:\n{}", format, text);
+        assert!(text.contains(name), "{} lost the name:\n{}", format, text);
+    }
+
+    /* the treemap is JS, so no string may have a raw line break */
+    let js = report(dir.path(), "cyclo.js", &["--format", "js"]);
+    assert!(js.contains(r#""project/src/we\tird\nname.c""#), "{}", js);
+    assert!(!js.contains("we\tird"), "{}", js);
+
+    /* line and tab separated formats replace the characters */
+    for format in ["markdown", "cccc", "dot"]
+    {
+        let text = report(dir.path(), &format!("report.{}", format), &["--format", format]);
+
+        assert!(text.contains("we?ird?name.c"), "{}:\n{}", format, text);
+        assert!(!text.contains("we\tird"), "{}:\n{}", format, text);
+    }
+
+    /* csv quotes the field instead */
+    let csv = report(dir.path(), "report.csv", &["--format", "csv"]);
+    assert!(csv.contains("\"project/src/we\tird\nname.c\""), "{}", csv);
+}
+
+#[test]
+fn long_treemap_labels_are_bounded()
+{
+    let dir = fixture();
+    let js = report(dir.path(), "cyclo.js", &["--format", "js", "--max-label-len", "12"]);
+
+    let labels = js.lines().find(|line| line.trim_start().starts_with("labels:")).unwrap();
+    assert!(labels.contains(r#""proje…main.c""#), "{}", labels);
+
+    /* the ids that parents refer to are the full paths */
+    let ids = js.lines().find(|line| line.trim_start().starts_with("ids:")).unwrap();
+    assert!(ids.contains(r#""project/main.c""#), "{}", ids);
+}
+
+#[test]
+fn reports_are_reproducible_byte_for_byte()
+{
+    let dir = tempfile::tempdir().unwrap();
+    let out = tempfile::tempdir().unwrap();
+    let names = ["b.c", "a.c", "c.py", "z/d.rs", "y/e.c"];
+
+    let mut runs = Vec::new();
+
+    /* the files are created in a different order for each run, so the
+     * directory listing order differs too */
+    for order in [names.to_vec(), names.iter().rev().copied().collect()]
+    {
+        for name in &names
+        {
+            let _ = fs::remove_file(dir.path().join(name));
+        }
+
+        for name in order
+        {
+            let path = dir.path().join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "int f(int x)\n{\n    if (x)\n    {\n        return 1;\n    }\n    return 0;\n}\n").unwrap();
+        }
+
+        let mut outputs = Vec::new();
+
+        for format in ["js", "json", "csv", "markdown"]
+        {
+            let file = out.path().join(format!("report.{}", format));
+
+            cyclo().env("SOURCE_DATE_EPOCH", "1700000000")
+                   .arg("-p").arg(dir.path())
+                   .args(["--format", format, "--compress-output", "-o"]).arg(&file)
+                   .assert()
+                   .success();
+
+            let mut gz = file.into_os_string();
+            gz.push(".gz");
+
+            outputs.push((format, fs::read(out.path().join(format!("report.{}", format))).unwrap(), fs::read(gz).unwrap()));
+        }
+
+        runs.push(outputs);
+    }
+
+    for (first, second) in runs[0].iter().zip(&runs[1])
+    {
+        assert!(first.1 == second.1, "{} differs between runs", first.0);
+        assert!(first.2 == second.2, "{}.gz differs between runs", first.0);
+    }
+
+    /* the pinned time is the one shown */
+    let js = String::from_utf8(runs[0][0].1.clone()).unwrap();
+    assert!(js.contains("2023-11-14 22:13 UTC"), "{}", js);
+}
+
+#[test]
+fn reports_without_timestamps_are_reproducible()
+{
+    let dir = fixture();
+    let out = tempfile::tempdir().unwrap();
+    let mut runs = Vec::new();
+
+    /* a minute apart would be enough to change the time, were it written */
+    for run in ["first.js", "second.js"]
+    {
+        let file = out.path().join(run);
+
+        cyclo().arg("-p").arg(dir.path())
+               .args(["--no-timestamps", "-o"]).arg(&file)
+               .assert()
+               .success();
+
+        runs.push(fs::read_to_string(file).unwrap());
+    }
+
+    assert_eq!(runs[0], runs[1]);
+    assert!(!runs[0].contains(" UTC"), "{}", runs[0]);
+    /* only the directory's name, not where it happens to be */
+    assert!(!runs[0].contains(&dir.path().display().to_string()), "{}", runs[0]);
+    assert!(runs[0].ends_with(&format!("subtitle: \"{} · cyclo {}\"}};\n", dir.path().file_name().unwrap().to_string_lossy(), env!("CARGO_PKG_VERSION"))), "{}", runs[0]);
+}
+
+#[test]
+#[cfg(feature = "sqlite")]
+fn sqlite_runs_are_timestamped_unless_asked_not_to()
+{
+    let dir = fixture();
+    let db = dir.path().join("history.db");
+
+    for flags in [&[][..], &["--no-timestamps"][..]]
+    {
+        cyclo().env("SOURCE_DATE_EPOCH", "1700000000")
+               .arg("-p").arg(dir.path())
+               .args(["--format", "csv", "-o"]).arg(dir.path().join("report.csv"))
+               .arg("--sqlite").arg(&db)
+               .args(flags)
+               .assert()
+               .success();
+    }
+
+    let connection = rusqlite::Connection::open(&db).unwrap();
+    let mut query = connection.prepare("SELECT timestamp FROM runs ORDER BY id").unwrap();
+    let timestamps: Vec<String> = query.query_map([], |row| row.get(0)).unwrap().map(Result::unwrap).collect();
+
+    assert_eq!(timestamps, ["2023-11-14T22:13:20Z", ""]);
+}
+
+/// The `--dry-run` lines for `dir`, with the directory taken off the paths
+fn dry_run(dir: &Path, args: &[&str]) -> Vec<String>
+{
+    let output = cyclo().arg("-p").arg(dir)
+                        .arg("--dry-run")
+                        .args(args)
+                        .output()
+                        .unwrap();
+
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let prefix = format!("{}/", dir.display());
+
+    String::from_utf8(output.stdout).unwrap()
+                                    .lines()
+                                    .map(|line| line.replace(&prefix, ""))
+                                    .collect()
+}
+
+#[test]
+fn ecosystem_directories_are_skipped_only_with_their_marker()
+{
+    let dir = tempfile::tempdir().unwrap();
+    fs::create_dir_all(dir.path().join("Pods").join("Alamofire")).unwrap();
+    fs::create_dir(dir.path().join("app")).unwrap();
+    fs::write(dir.path().join("Pods").join("Alamofire").join("Session.swift"), "func f() {}\n").unwrap();
+    fs::write(dir.path().join("app").join("main.swift"), "func f() {}\n").unwrap();
+
+    let without = dry_run(dir.path(), &[]);
+    assert!(without.iter().any(|line| line.starts_with("analyze\tPods/Alamofire/Session.swift\t")), "{:?}", without);
+
+    fs::write(dir.path().join("Podfile"), "").unwrap();
+
+    let with = dry_run(dir.path(), &[]);
+    assert!(with.contains(&"skip\tPods\tauto-excluded 'Pods' because Podfile is present".to_string()), "{:?}", with);
+    assert!(!with.iter().any(|line| line.contains("Session.swift")), "{:?}", with);
+    assert!(with.iter().any(|line| line.starts_with("analyze\tapp/main.swift\t")), "{:?}", with);
+
+    let opted_out = dry_run(dir.path(), &["--no-auto-exclude"]);
+    assert!(opted_out.iter().any(|line| line.starts_with("analyze\tPods/Alamofire/Session.swift\t")), "{:?}", opted_out);
+}
+
+#[test]
+fn rewrite_collisions_trace_both_files()
+{
+    let dir = tempfile::tempdir().unwrap();
+
+    for name in ["a", "b"]
+    {
+        fs::create_dir(dir.path().join(name)).unwrap();
+        fs::write(dir.path().join(name).join("x.c"), "int f(void)\n{\n    return 0;\n}\n").unwrap();
+    }
+
+    let output = cyclo().arg("-p").arg(dir.path())
+                        .args(["--root-label", "t", "--path-rewrite", "from=t/a,to=lib", "--path-rewrite", "from=t/b,to=lib"])
+                        .args(["--errors-format", "json", "--format", "csv", "-o"]).arg(dir.path().join("report.csv"))
+                        .output()
+                        .unwrap();
+
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let diagnostic: serde_json::Value = stderr.lines()
+                                              .filter_map(|line| serde_json::from_str::<serde_json::Value>(line).ok())
+                                              .find(|d| d["code"] == "E_PATH_REWRITE_COLLISION")
+                                              .unwrap_or_else(|| panic!("no collision in {}", stderr));
+
+    let a = dir.path().join("a").join("x.c");
+    let b = dir.path().join("b").join("x.c");
+
+    assert_eq!(diagnostic["path"], b.display().to_string());
+    assert_eq!(diagnostic["message"], format!("rewritten to the same label as another file, so this one was left out: path {} -> label t/b/x.c -> --path-rewrite lib/x.c (first: path {} -> label t/a/x.c -> --path-rewrite lib/x.c)",
+                                              b.display(), a.display()));
+
+    /* only the first file is in the report */
+    let csv = fs::read_to_string(dir.path().join("report.csv")).unwrap();
+    assert_eq!(csv.matches("lib/x.c").count(), 1, "{}", csv);
+}
+
+#[test]
+fn charts_carry_their_provenance()
+{
+    let dir = fixture();
+    let out = dir.path().join("cyclo.js");
+
+    cyclo().env("SOURCE_DATE_EPOCH", "1700000000")
+           .arg("-p").arg(dir.path())
+           .args(["--root-label", "project", "-o"]).arg(&out)
+           .assert()
+           .success();
+
+    let js = fs::read_to_string(&out).unwrap();
+    let expected = format!("var jsondata_header = {{title: \"project\", subtitle: \"{} · 2023-11-14 22:13 UTC · cyclo {}\"}};\n",
+                           dir.path().file_name().unwrap().to_string_lossy(), env!("CARGO_PKG_VERSION"));
+
+    assert!(js.ends_with(&expected), "{}", js);
+
+    cyclo().arg("-p").arg(dir.path())
+           .args(["--title", "Firmware", "--subtitle", "release 2.1", "-o"]).arg(&out)
+           .assert()
+           .success();
+
+    let js = fs::read_to_string(&out).unwrap();
+    assert!(js.ends_with("var jsondata_header = {title: \"Firmware\", subtitle: \"release 2.1\"};\n"), "{}", js);
+}
+
+/// The JSON diagnostics of a run and its exit code. Every line has to parse
+/// and have the four fields, with a severity that matches the code
+fn diagnostics(cmd: &mut Command) -> (Vec<serde_json::Value>, Option<i32>)
+{
+    let output = cmd.args(["--errors-format", "json"]).output().unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+
+    let lines: Vec<serde_json::Value> = stderr.lines()
+                                              .map(|line| serde_json::from_str(line).unwrap_or_else(|e| panic!("{}: {}", e, line)))
+                                              .collect();
+
+    for line in &lines
+    {
+        let code = line["code"].as_str().unwrap();
+        let severity = match &code[..2]
+        {
+            "E_" => "error",
+            "W_" => "warning",
+            "I_" => "info",
+            _ => panic!("bad code {}", code),
+        };
+
+        assert_eq!(line["severity"], severity, "{}", line);
+        assert!(line["message"].is_string(), "{}", line);
+        assert!(line["path"].is_string() || line["path"].is_null(), "{}", line);
+    }
+
+    (lines, output.status.code())
+}
+
+/// Whether a diagnostic with `code` was given, and for `path` if that is set
+fn has(lines: &[serde_json::Value], code: &str, path: Option<&Path>) -> bool
+{
+    lines.iter().any(|line| line["code"] == code && path.is_none_or(|path| line["path"] == path.display().to_string()))
+}
+
+#[test]
+fn diagnostics_have_stable_codes_end_to_end()
+{
+    let dir = fixture();
+    fs::create_dir(dir.path().join("node_modules")).unwrap();
+    fs::write(dir.path().join("notes.txt"), "hi\n").unwrap();
+    fs::write(dir.path().join("cyclo.toml"), "colour = \"green\"\n").unwrap();
+
+    let out = dir.path().join("cyclo.js");
+
+    let (lines, status) = diagnostics(cyclo().arg("-p").arg(dir.path())
+                                             .args(["--exclude", "nothing/**", "-o"]).arg(&out));
+
+    assert_eq!(status, Some(0));
+    assert!(has(&lines, "W_CONFIG_KEY", Some(&dir.path().join("cyclo.toml"))), "{:?}", lines);
+    assert!(has(&lines, "I_SKIPPED", Some(&dir.path().join("node_modules"))), "{:?}", lines);
+    assert!(has(&lines, "I_SKIPPED", Some(&dir.path().join("notes.txt"))), "{:?}", lines);
+    assert!(has(&lines, "W_UNSUPPORTED", None), "{:?}", lines);
+    assert!(has(&lines, "W_EXCLUDE_UNMATCHED", None), "{:?}", lines);
+    assert!(!lines.iter().any(|line| line["severity"] == "error"), "{:?}", lines);
+
+    /* a gate exits with 3, unlike an error */
+    let (lines, status) = diagnostics(cyclo().arg("-p").arg(dir.path())
+                                             .args(["--fail-above", "0", "-o"]).arg(&out));
+
+    assert_eq!(status, Some(3));
+    assert!(has(&lines, "E_FAIL_ABOVE", None), "{:?}", lines);
+    assert!(has(&lines, "E_GATE", None), "{:?}", lines);
+
+    /* an output under a file can't be written, which is found before parsing */
+    let blocked = dir.path().join("main.c").join("cyclo.js");
+    let (lines, status) = diagnostics(cyclo().arg("-p").arg(dir.path())
+                                             .arg("-o").arg(&blocked));
+
+    assert_eq!(status, Some(1));
+    assert!(has(&lines, "E_WRITE", Some(&blocked)), "{:?}", lines);
+
+    /* errors found before anything is walked are diagnostics too */
+    let (lines, status) = diagnostics(cyclo().arg("-p").arg(dir.path().join("missing")));
+
+    assert_eq!(status, Some(1));
+    assert!(has(&lines, "E_PATH", None), "{:?}", lines);
+
+    let (lines, status) = diagnostics(cyclo().arg("-p").arg(dir.path().join("notes.txt")));
+
+    assert_eq!(status, Some(1));
+    assert!(has(&lines, "E_PARSE", Some(&dir.path().join("notes.txt"))), "{:?}", lines);
+
+    let (lines, status) = diagnostics(cyclo().arg("-p").arg(dir.path())
+                                             .args(["--exclude", "[", "-o"]).arg(&out));
+
+    assert_eq!(status, Some(1));
+    assert!(has(&lines, "E_CONFIG", None), "{:?}", lines);
+
+    let (lines, status) = diagnostics(cyclo().arg("--files-from").arg(dir.path().join("list.txt")));
+
+    assert_eq!(status, Some(1));
+    assert!(has(&lines, "E_FILES_FROM", Some(&dir.path().join("list.txt"))), "{:?}", lines);
+
+    fs::write(dir.path().join("list.txt"), "gone.c\n").unwrap();
+    let (lines, status) = diagnostics(cyclo().current_dir(dir.path()).args(["--files-from", "list.txt"]));
+
+    assert_eq!(status, Some(1));
+    assert!(has(&lines, "W_FILES_FROM", None), "{:?}", lines);
+    assert!(has(&lines, "E_FILES_FROM", Some(Path::new("list.txt"))), "{:?}", lines);
+
+    fs::write(dir.path().join("cyclo.toml"), "exclude = 3\n").unwrap();
+    let (lines, status) = diagnostics(cyclo().arg("-p").arg(dir.path())
+                                             .arg("-o").arg(&out));
+
+    assert_eq!(status, Some(1));
+    assert!(has(&lines, "E_CONFIG", None), "{:?}", lines);
+}
+
+#[test]
+fn diagnostics_can_go_to_a_file()
+{
+    let dir = fixture();
+    let errors = dir.path().join("errors.jsonl");
+    fs::write(dir.path().join("notes.txt"), "hi\n").unwrap();
+
+    cyclo().arg("-p").arg(dir.path())
+           .args(["--errors-format", "json", "--errors-file"]).arg(&errors)
+           .arg("-o").arg(dir.path().join("cyclo.js"))
+           .assert()
+           .success();
+
+    let text = fs::read_to_string(&errors).unwrap();
+    let lines: Vec<serde_json::Value> = text.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+
+    assert!(has(&lines, "W_UNSUPPORTED", None), "{}", text);
+}
+
+#[test]
+fn dry_run_lists_what_a_run_analyzes()
+{
+    let dir = fixture();
+    fs::create_dir_all(dir.path().join("vendor").join("x")).unwrap();
+    fs::write(dir.path().join("vendor").join("x").join("v.c"), "int v(void)\n{\n    return 0;\n}\n").unwrap();
+    fs::write(dir.path().join("notes.txt"), "hi\n").unwrap();
+
+    let lines = dry_run(dir.path(), &["--exclude", "vendor/**"]);
+
+    assert_eq!(lines, ["analyze\tmain.c\tc\t109",
+                       "skip\tnotes.txt\tunsupported extension",
+                       "analyze\tsrc/we?ird?name.c\tc\t70",
+                       "skip\tvendor\texcluded by 'vendor/**'",
+                       "2 files would be analyzed, skipped 2 entries (excluded by 'vendor/**': 1, unsupported extension: 1)",
+                       "skipped 1 file: .txt (1, 3 B) - these languages are not yet supported"]);
+
+    /* nothing is written on a dry run */
+    assert!(!dir.path().join("cyclo.js").exists());
+
+    let csv = report(dir.path(), "report.csv", &["--format", "csv", "--exclude", "vendor/**"]);
+    let files: Vec<&str> = csv.lines().filter(|line| line.starts_with("file,")).collect();
+
+    assert_eq!(files.len(), 2, "{}", csv);
+
+    /* generated and vendored files left out by a real run are skipped in a dry run too */
+    fs::write(dir.path().join("moc_window.cpp"), "int m(void)\n{\n    return 0;\n}\n").unwrap();
+    fs::write(dir.path().join("zlib.c"), "/* Copyright (C) Jean-loup Gailly */\nint z(void)\n{\n    return 0;\n}\n").unwrap();
+
+    let excluding = ["--exclude", "vendor/**", "--detect-generated", "exclude", "--detect-vendored", "exclude"];
+    let lines = dry_run(dir.path(), &excluding);
+
+    assert!(lines.contains(&"skip\tmoc_window.cpp\tgenerated (--detect-generated exclude)".to_string()), "{:?}", lines);
+    assert!(lines.contains(&"skip\tzlib.c\tvendored (--detect-vendored exclude)".to_string()), "{:?}", lines);
+    assert!(lines.iter().any(|line| line.starts_with("2 files would be analyzed")), "{:?}", lines);
+
+    let csv = report(dir.path(), "report.csv", &[&excluding[..], &["--format", "csv"]].concat());
+    let files: Vec<&str> = csv.lines().filter(|line| line.starts_with("file,")).collect();
+
+    assert_eq!(files.len(), 2, "{}", csv);
+}
+
+/// 100k files twelve directories deep. Every file adds its missing ancestors,
+/// so this is where looking labels up by scanning went quadratic. Run with
+/// `cargo test --release -- --ignored deep_trees` to see the time
+#[test]
+#[ignore]
+fn deep_trees_are_synthesized_in_reasonable_time()
+{
+    const FILES: usize = 100_000;
+    const DEPTH: usize = 12;
+    const PER_DIR: usize = 8;
+
+    let dir = tempfile::tempdir().unwrap();
+    let mut dirs = std::collections::HashSet::new();
+
+    for group in 0..FILES / PER_DIR
+    {
+        /* the group number in base 3, one digit per level */
+        let mut path = dir.path().to_path_buf();
+        let mut rest = group;
+        let mut label = String::new();
+
+        for _ in 0..DEPTH
+        {
+            label.push_str(&format!("/d{}", rest % 3));
+            path.push(format!("d{}", rest % 3));
+            dirs.insert(label.clone());
+            rest /= 3;
+        }
+
+        fs::create_dir_all(&path).unwrap();
+
+        for file in 0..PER_DIR
+        {
+            fs::write(path.join(format!("f{}.c", file)), "int f(int x)\n{\n    return x ? 1 : 0;\n}\n").unwrap();
+        }
+    }
+
+    let started = std::time::Instant::now();
+    let csv = report(dir.path(), "report.csv", &["--format", "csv"]);
+    let elapsed = started.elapsed();
+
+    eprintln!("{} files in {} directories took {:?}", FILES, dirs.len(), elapsed);
+
+    assert_eq!(csv.lines().filter(|line| line.starts_with("file,")).count(), FILES);
+    /* the root is a directory record too */
+    assert_eq!(csv.lines().filter(|line| line.starts_with("dir,")).count(), dirs.len() + 1);
+    assert!(elapsed < std::time::Duration::from_secs(60), "took {:?}", elapsed);
+}
+
+#[test]
+fn dry_runs_can_be_json_lines()
+{
+    let dir = fixture();
+    fs::write(dir.path().join("notes.txt"), "hi\n").unwrap();
+
+    let lines: Vec<serde_json::Value> = dry_run(dir.path(), &["--format", "json"]).iter()
+                                                                                 .map(|line| serde_json::from_str(line).unwrap_or_else(|e| panic!("{}: {}", e, line)))
+                                                                                 .collect();
+
+    assert_eq!(lines, [serde_json::json!({"action": "analyze", "path": "main.c", "language": "c", "size": 109}),
+                       serde_json::json!({"action": "skip", "path": "notes.txt", "reason": "unsupported extension", "size": 3}),
+                       serde_json::json!({"action": "analyze", "path": "src/we\tird\nname.c", "language": "c", "size": 70})]);
+}
@@ -0,0 +1,16 @@
+/* fixture: invalid UTF-8 inside an identifier and string literals */
+#include <stdio.h>
+
+static int caf_count = 0;
+
+int nave_lookup(int key)
+{
+    if (key > 0 && caf_count < 10)
+    {
+        printf("rsum %d\n", key);
+        return 1;
+    }
+
+    puts("bad  bytes");
+    return 0;
+}
@@ -1,10 +1,16 @@
 use std::net::{TcpListener, TcpStream, SocketAddr};
 use std::path::Path;
-use std::io::{Read, Write};
-use std::fs;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::fs::File;
 use clap::Parser;
+use sha2::{Digest, Sha256};
 
 
+/// The vendored plotly bundle that index.html loads, and its sha256. The page
+/// is useless without it, so it is checked at startup
+const PLOTLY_PATH: &str = "./web/static/plotly-2.14.0.min.js";
+const PLOTLY_SHA256: &str = "566c9e4b03a631480c5cc0e37d4ee8b8b855fc4159b8e884fdfe827d2a4bba3c";
+
 #[derive(Parser,Debug)]
 #[clap(name="webserver")]
 struct Args
@@ -19,7 +25,8 @@ struct Args
 struct HttpRequest
 {
     method: String,
-    uri: String
+    uri: String,
+    headers: Vec<(String, String)>
 }
 
 impl HttpRequest
@@ -38,8 +45,138 @@ impl HttpRequest
          * not sure why this is happening? */
         let uri = stat[1].to_string();
 
-        HttpRequest { method, uri }
+        /* the rest are headers like Range: bytes=0-99, up to the blank line */
+        let headers = req.get(1).unwrap_or(&"")
+                                .split("\r\n")
+                                .take_while(|line| !line.is_empty())
+                                .filter_map(|line| line.split_once(':'))
+                                .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+                                .collect();
+
+        HttpRequest { method, uri, headers }
+    }
+
+    /// Get the value of a header, ignoring the case of its name
+    fn header(&self, name: &str) -> Option<&str>
+    {
+        self.headers.iter()
+                    .find(|(n, _)| n.eq_ignore_ascii_case(name))
+                    .map(|(_, v)| v.as_str())
+    }
+}
+
+/// Parse a single `Range: bytes=start-end` header value against a file of
+/// `len` bytes, returning the inclusive byte range. Returns None if the range
+/// can't be satisfied. Multiple ranges aren't supported
+fn parse_range(value: &str, len: u64) -> Option<(u64, u64)>
+{
+    let spec = value.trim().strip_prefix("bytes=")?;
+
+    if spec.contains(',') || len == 0
+    {
+        return None;
+    }
+
+    let (start, end) = spec.split_once('-')?;
+
+    let (start, end) = if start.is_empty()
+    {
+        /* bytes=-500 is the last 500 bytes */
+        let suffix: u64 = end.parse().ok()?;
+
+        if suffix == 0
+        {
+            return None;
+        }
+
+        (len.saturating_sub(suffix), len - 1)
+    }
+    else
+    {
+        let start: u64 = start.parse().ok()?;
+        let end: u64 = if end.is_empty() { len - 1 } else { end.parse().ok()? };
+
+        (start, end.min(len - 1))
+    };
+
+    if start > end
+    {
+        return None;
+    }
+
+    Some((start, end))
+}
+
+/// Write a response whose body is streamed from a file rather than read into
+/// memory, so very large reports don't stall the server
+fn send_file(stream: &mut impl Write, request: &HttpRequest, path: &str) -> io::Result<()>
+{
+    let mime_type = Path::new(path).extension().unwrap().to_string_lossy();
+    let mime_type = if mime_type == "js"
+    {
+        "javascript".to_string()
+    }
+    else
+    {
+        mime_type.to_string()
+    };
+
+    let content_type = format!("text/{}", mime_type);
+
+    let range = request.header("Range");
+
+    /* a pre-compressed sidecar generated by cyclo is used if the client takes
+     * gzip. ranges are only served from the uncompressed file */
+    let gzip_path = format!("{}.gz", path);
+    let accepts_gzip = request.header("Accept-Encoding")
+                              .map(|v| v.split(',').any(|e| e.trim().starts_with("gzip")))
+                              .unwrap_or(false);
+
+    if range.is_none() && accepts_gzip && Path::new(&gzip_path).exists()
+    {
+        let mut file = File::open(&gzip_path)?;
+        let len = file.metadata()?.len();
+
+        write!(stream,
+               "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nContent-Type: {}\r\nContent-Encoding: gzip\r\nVary: Accept-Encoding\r\n\r\n",
+               len, content_type)?;
+        io::copy(&mut file, stream)?;
+
+        return Ok(());
+    }
+
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len();
+
+    match range
+    {
+        Some(value) => {
+            match parse_range(value, len)
+            {
+                Some((start, end)) => {
+                    file.seek(SeekFrom::Start(start))?;
+
+                    write!(stream,
+                           "HTTP/1.1 206 Partial Content\r\nContent-Length: {}\r\nContent-Type: {}\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+                           end - start + 1, content_type, start, end, len)?;
+                    io::copy(&mut file.take(end - start + 1), stream)?;
+                },
+                None => {
+                    write!(stream,
+                           "HTTP/1.1 416 Range Not Satisfiable\r\nContent-Range: bytes */{}\r\nContent-Length: 0\r\n\r\n",
+                           len)?;
+                }
+            }
+        },
+        None => {
+            write!(stream,
+                   "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nContent-Type: {}\r\nAccept-Ranges: bytes\r\n\r\n",
+                   len, content_type)?;
+            io::copy(&mut file, stream)?;
+        }
     }
+
+    Ok(())
 }
 
 /// Handle the HTTP request
@@ -47,67 +184,84 @@ fn handle_connection(mut stream: TcpStream)
 {
     let mut buf = vec![0;2048];
 
-    stream.read(&mut buf).unwrap();
+    let n = stream.read(&mut buf).unwrap();
 
-    let request_data = String::from_utf8_lossy(&buf);
+    let request_data = String::from_utf8_lossy(&buf[..n]);
     let request = HttpRequest::new(request_data.to_string());
 
-    let response = if request.method == "GET"
+    if request.method == "GET"
     {
 
         // parse the URI so if the user navigates to it, it'll just hit a 404
-        let filename: String = if request.uri == "/"
+        let filename: &str = if request.uri == "/"
         {
-            "index.html".to_string()
+            "index.html"
         }
         else
         {
-            request.uri
+            &request.uri
         };
 
         let path = format!("./web/{}", filename);
 
-        if Path::new(&path).exists()
+        if Path::new(&path).is_file()
         {
-            let content = fs::read_to_string(&path).unwrap();
-
-            let mime_type = Path::new(&path).extension().unwrap().to_string_lossy();
-            let mime_type = if mime_type == "js"
+            if let Err(e) = send_file(&mut stream, &request, &path)
             {
-                "javascript".to_string()
+                eprintln!("failed to send {}: {:?}", path, e);
             }
-            else
-            {
-                mime_type.to_string()
-            };
-
-            let content_type = format!("text/{}", mime_type);
-
-            // response
-            format!(
-                "HTTP/1.1 200 OK\r\nContent-Length: {content_length}\r\nContent-Type: {content_type}\r\n\r\n{body}",
-                content_length=content.len(),
-                content_type=content_type,
-                body=content)
         }
         else
         {
-            "HTTP/1.1 404 Not Found\r\n\r\nNot Found".to_string()
+            stream.write_all(b"HTTP/1.1 404 Not Found\r\n\r\nNot Found").unwrap();
         }
     }
     else
     {
-        "HTTP/1.1 501 Not Implemented\r\n\r\nNot Implemented".to_string()
-    };
+        stream.write_all(b"HTTP/1.1 501 Not Implemented\r\n\r\nNot Implemented").unwrap();
+    }
 
-    stream.write(response.as_bytes()).unwrap();
     stream.flush().unwrap();
 }
 
+/// Warn if the vendored plotly bundle is missing or isn't the expected file.
+/// The server still starts, and the page explains what is wrong
+fn check_plotly()
+{
+    let mut file = match File::open(PLOTLY_PATH)
+    {
+        Ok(file) => file,
+        Err(error) => {
+            eprintln!("warning: {} could not be opened ({}), the chart won't render", PLOTLY_PATH, error);
+            return;
+        }
+    };
+
+    let mut hasher = Sha256::new();
+
+    if let Err(error) = io::copy(&mut file, &mut hasher)
+    {
+        eprintln!("warning: {} could not be read ({}), the chart won't render", PLOTLY_PATH, error);
+        return;
+    }
+
+    let digest: String = hasher.finalize()
+                               .iter()
+                               .map(|b| format!("{:02x}", b))
+                               .collect();
+
+    if digest != PLOTLY_SHA256
+    {
+        eprintln!("warning: {} doesn't match the vendored copy (sha256 {}), restore it from the repository", PLOTLY_PATH, digest);
+    }
+}
+
 fn main()
 {
     let args = Args::parse();
 
+    check_plotly();
+
     // if args.port fails, bind to 3030
     let addrs = [
         SocketAddr::from(([127,0,0,1], args.port)),
@@ -131,3 +285,159 @@ fn main()
         handle_connection(stream);
     }
 }
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use std::fs;
+
+    fn request(headers: &str) -> HttpRequest
+    {
+        HttpRequest::new(format!("GET /scripts/cyclo.js HTTP/1.1\r\n{}\r\n", headers))
+    }
+
+    /// Serve `path` for a request with `headers`, returning the response head
+    /// and body
+    fn serve(path: &Path, headers: &str) -> (String, Vec<u8>)
+    {
+        let mut response = Vec::new();
+        send_file(&mut response, &request(headers), path.to_str().unwrap()).unwrap();
+
+        let split = response.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+        let body = response.split_off(split);
+
+        (String::from_utf8(response).unwrap(), body)
+    }
+
+    #[test]
+    fn ranges()
+    {
+        assert_eq!(parse_range("bytes=0-99", 1000), Some((0, 99)));
+        assert_eq!(parse_range("bytes=900-", 1000), Some((900, 999)));
+        assert_eq!(parse_range("bytes=-100", 1000), Some((900, 999)));
+        assert_eq!(parse_range("bytes=-5000", 1000), Some((0, 999)));
+        assert_eq!(parse_range("bytes=500-5000", 1000), Some((500, 999)));
+        assert_eq!(parse_range(" bytes=1-1 ", 1000), Some((1, 1)));
+    }
+
+    #[test]
+    fn unsatisfiable_ranges()
+    {
+        assert_eq!(parse_range("bytes=1000-", 1000), None);
+        assert_eq!(parse_range("bytes=5-4", 1000), None);
+        assert_eq!(parse_range("bytes=-0", 1000), None);
+        assert_eq!(parse_range("bytes=0-1,5-6", 1000), None);
+        assert_eq!(parse_range("bytes=0-", 0), None);
+        assert_eq!(parse_range("items=0-1", 1000), None);
+        assert_eq!(parse_range("bytes=a-b", 1000), None);
+    }
+
+    #[test]
+    fn headers_are_found_in_any_case()
+    {
+        let request = request("range: bytes=0-1\r\nAccept-Encoding: gzip, br\r\n");
+
+        assert_eq!(request.header("Range"), Some("bytes=0-1"));
+        assert_eq!(request.header("accept-encoding"), Some("gzip, br"));
+        assert_eq!(request.header("Cookie"), None);
+    }
+
+    #[test]
+    fn partial_content_is_served()
+    {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cyclo.js");
+        fs::write(&path, "0123456789").unwrap();
+
+        let (head, body) = serve(&path, "Range: bytes=2-5\r\n");
+        assert!(head.starts_with("HTTP/1.1 206 Partial Content\r\n"), "{}", head);
+        assert!(head.contains("Content-Range: bytes 2-5/10\r\n"), "{}", head);
+        assert!(head.contains("Content-Length: 4\r\n"), "{}", head);
+        assert_eq!(body, b"2345");
+
+        let (head, body) = serve(&path, "Range: bytes=20-\r\n");
+        assert!(head.starts_with("HTTP/1.1 416 Range Not Satisfiable\r\n"), "{}", head);
+        assert!(head.contains("Content-Range: bytes */10\r\n"), "{}", head);
+        assert!(body.is_empty());
+
+        let (head, body) = serve(&path, "");
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{}", head);
+        assert!(head.contains("Accept-Ranges: bytes\r\n"), "{}", head);
+        assert!(head.contains("Content-Type: text/javascript\r\n"), "{}", head);
+        assert_eq!(body, b"0123456789");
+    }
+
+    #[test]
+    fn gzip_sidecar_is_only_served_whole()
+    {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cyclo.js");
+        fs::write(&path, "0123456789").unwrap();
+        fs::write(dir.path().join("cyclo.js.gz"), "compressed").unwrap();
+
+        let (head, body) = serve(&path, "Accept-Encoding: deflate, gzip\r\n");
+        assert!(head.contains("Content-Encoding: gzip\r\n"), "{}", head);
+        assert!(head.contains("Vary: Accept-Encoding\r\n"), "{}", head);
+        assert_eq!(body, b"compressed");
+
+        let (head, body) = serve(&path, "Accept-Encoding: gzip\r\nRange: bytes=0-1\r\n");
+        assert!(!head.contains("Content-Encoding"), "{}", head);
+        assert_eq!(body, b"01");
+
+        let (_, body) = serve(&path, "Accept-Encoding: br\r\n");
+        assert_eq!(body, b"0123456789");
+    }
+
+    /// Counts what is written to it and keeps none of it
+    struct Sink(u64);
+
+    impl Write for Sink
+    {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize>
+        {
+            self.0 += buf.len() as u64;
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()>
+        {
+            Ok(())
+        }
+    }
+
+    /// The peak resident memory of this process in kB
+    #[cfg(target_os = "linux")]
+    fn peak_rss() -> u64
+    {
+        let status = fs::read_to_string("/proc/self/status").unwrap();
+        let line = status.lines().find(|line| line.starts_with("VmHWM:")).unwrap();
+
+        line.split_whitespace().nth(1).unwrap().parse().unwrap()
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn large_files_are_streamed_in_flat_memory()
+    {
+        const SIZE: u64 = 512 * 1024 * 1024;
+
+        /* sparse, so it takes no disk space */
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cyclo.js");
+        File::create(&path).unwrap().set_len(SIZE).unwrap();
+
+        let before = peak_rss();
+
+        let mut sink = Sink(0);
+        send_file(&mut sink, &request(""), path.to_str().unwrap()).unwrap();
+
+        let mut range = Sink(0);
+        send_file(&mut range, &request("Range: bytes=1024-\r\n"), path.to_str().unwrap()).unwrap();
+
+        let grown = peak_rss() - before;
+
+        assert!(sink.0 > SIZE && range.0 > SIZE - 1024);
+        assert!(grown < 64 * 1024, "peak memory grew by {} kB serving {} MB", grown, SIZE / 1024 / 1024);
+    }
+}
@@ -1,4 +1,39 @@
 const element = document.getElementById("plot");
 
-// jsondata is from plot.js
-Plotly.newPlot(element, jsondata)
+// plotly is vendored in web/static. without it the page would be blank, so
+// say what is missing instead
+if (typeof Plotly === "undefined")
+{
+    const banner = document.createElement("p");
+    banner.className = "error";
+    banner.textContent = "plotly.js could not be loaded. Check that web/static/plotly-2.14.0.min.js "
+                       + "exists and is served by the webserver, then reload.";
+    element.replaceWith(banner);
+}
+else if (typeof jsondata === "undefined")
+{
+    const banner = document.createElement("p");
+    banner.className = "error";
+    banner.textContent = "No chart data found. Run cyclo to generate web/scripts/cyclo.js, then reload.";
+    element.replaceWith(banner);
+}
+else
+{
+    // older cyclo.js files have no header
+    const header = typeof jsondata_header === "undefined" ? null : jsondata_header;
+    const layout = {};
+
+    if (header !== null)
+    {
+        // plotly renders titles as html, so the text is escaped first
+        const title = document.createElement("span");
+        title.textContent = header.title;
+        layout.title = {text: title.innerHTML};
+
+        document.title = header.title + " - cyclomatic complexity";
+        document.getElementById("footer").textContent = header.subtitle;
+    }
+
+    // jsondata is from cyclo.js
+    Plotly.newPlot(element, jsondata, layout)
+}